@@ -1,17 +1,27 @@
+use crate::engine::Action;
+use crate::engine::{Death, Engine, GameEvent, Killer};
+use crate::frame::Screen;
+use crate::keymap::Keymap;
+use crate::robot_kind::RobotKind;
 use crate::structs::*;
+use crate::theme::{Entity, Theme};
 use crossterm::{
     cursor::MoveTo,
     event::{read, Event, KeyCode},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
+    terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType},
 };
 /// This module contains functions related to displaying the game interface and graphics.
 ///
 /// The `display` module provides functions for drawing various game elements on the screen,
 /// such as the player, robots, junk heaps, and boundaries. It also includes a splash screen
 /// function for displaying the game's introduction and a retry query function for prompting
-/// the user to try again.
-use std::io::{self, prelude::*};
+/// the user to try again. When the terminal is too small for the board, it asks the user to
+/// enlarge it instead.
+///
+/// The board and the side panel are drawn on a `Screen`, which only sends the changes to the
+/// terminal once the frame is presented. The things on the board are coloured by the `Theme`.
+use std::io;
 
 pub fn move_cursor_padded(x: i32, y: i32) {
     execute!(
@@ -24,140 +34,445 @@ pub fn move_cursor_padded(x: i32, y: i32) {
     .unwrap();
 }
 
+// Puts text on the screen, with the same padding as `move_cursor_padded`
+pub fn print_padded(screen: &mut Screen, x: i32, y: i32, text: &str) {
+    screen.print(x + PADDING_LEFT, y + PADDING_TOP, text);
+}
+
+// Puts the glyph of an entity on the board, in the colours the theme has for it
+fn draw_entity(screen: &mut Screen, theme: &Theme, entity: Entity, x: i32, y: i32) {
+    let glyph = theme.glyph(entity).to_string();
+    screen.print_styled(
+        x + PADDING_LEFT,
+        y + PADDING_TOP,
+        &glyph,
+        theme.style(entity),
+    );
+}
+
+/// The side panel is at least this many lines high, with the score on the last line. That is
+/// enough for the commands, the legend below them only shows as far as the board is high.
+const PANEL_HEIGHT: i32 = 15;
+
+/// The columns the side panel needs to the right of the board, including the gap to the border.
+const SIDE_PANEL_WIDTH: i32 = 40;
+
+// The line of the side panel that holds the score, which lines up with the bottom of the board
+fn panel_bottom(size: BoardSize) -> i32 {
+    size.height.max(PANEL_HEIGHT)
+}
+
+// Prints a line in the side panel, counting up from the score at the bottom of the panel
+pub fn draw_panel_line(screen: &mut Screen, size: BoardSize, lines_up: i32, text: &str) {
+    print_padded(screen, size.width + 4, panel_bottom(size) - lines_up, text);
+}
+
+/// The size the terminal needs to be to show a board of this size with the side panel.
+pub fn required_terminal_size(size: BoardSize) -> (u16, u16) {
+    (
+        (PADDING_LEFT + size.width + 2 + SIDE_PANEL_WIDTH) as u16,
+        (PADDING_TOP + panel_bottom(size) + 3) as u16,
+    )
+}
+
+/// The largest board that fits in a terminal of this size, the opposite of
+/// `required_terminal_size`. The side panel is as high as the board, as long as the board is at
+/// least as high as the commands.
+pub fn fitting_board_size(columns: u16, rows: u16) -> BoardSize {
+    // Leave room for the padding, the border, the side menu and the line below the board
+    BoardSize {
+        width: (columns as i32 - PADDING_LEFT - 2 - SIDE_PANEL_WIDTH).max(MIN_BOARD_WIDTH),
+        height: (rows as i32 - PADDING_TOP - 3).max(MIN_BOARD_HEIGHT),
+    }
+}
+
+// Whether the board fits in the terminal. If the size can't be read we just try our best.
+pub fn terminal_fits(size: BoardSize) -> bool {
+    let (columns, rows) = required_terminal_size(size);
+    match terminal::size() {
+        Ok((width, height)) => width >= columns && height >= rows,
+        Err(_) => true,
+    }
+}
+
+// Asks the player to enlarge the terminal, instead of drawing a board that doesn't fit
+pub fn draw_too_small(screen: &mut Screen, keymap: &Keymap, size: BoardSize) {
+    let (columns, rows) = required_terminal_size(size);
+    let (width, height) = terminal::size().unwrap_or((0, 0));
+    screen.clear();
+    screen.print(0, 0, "The terminal is too small for the board.");
+    let enlarge = format!("Please enlarge it to at least {}x{}", columns, rows);
+    screen.print(0, 1, &enlarge);
+    let now = format!(
+        "(it is {}x{} now), or press {} to quit.",
+        width,
+        height,
+        keymap.label(Action::Quit)
+    );
+    screen.print(0, 2, &now);
+    screen.present();
+}
+
 pub fn draw_active_objects(
-    player: &Player, dumb_robots: &Vec<DumbRobot>, junk_heaps: &Vec<JunkHeap>, item: &Item,
+    screen: &mut Screen, theme: &Theme, player: &Player, dumb_robots: &[DumbRobot],
+    junk_heaps: &[JunkHeap], item: &Item, death: Option<&Death>,
 ) {
     // Draw the item, if it is visible and not picked up
     if item.visible && !item.picked_up {
-        move_cursor_padded(item.pos_x, item.pos_y);
         if item.kind == 1 {
-            print!("S");
+            draw_entity(
+                screen,
+                theme,
+                Entity::SafeTeleportItem,
+                item.pos_x,
+                item.pos_y,
+            );
         } else if item.kind == 2 {
-            print!("B");
+            draw_entity(screen, theme, Entity::BombItem, item.pos_x, item.pos_y);
         }
     }
     // Draw the player
-    move_cursor_padded(player.pos_x, player.pos_y);
-    print!("@");
+    draw_entity(screen, theme, Entity::Player, player.pos_x, player.pos_y);
 
     // Draw the robots
     for robot in dumb_robots {
-        move_cursor_padded(robot.pos_x, robot.pos_y);
         if !robot.is_scrap {
-            // Separate the robots by kind
-            if robot.kind == 1 {
-                print!("+");
-            } else if robot.kind == 2 {
-                print!("&");
-            } else if robot.kind == 3 {
-                print!("N");
-            }
+            let entity = if robot.damaged {
+                Entity::DamagedRobot
+            } else {
+                Entity::Robot(robot.kind)
+            };
+            draw_entity(screen, theme, entity, robot.pos_x, robot.pos_y);
         }
     }
 
     // Draw the junk heaps
     for junk in junk_heaps {
-        move_cursor_padded(junk.pos_x, junk.pos_y);
-        print!("#");
+        draw_entity(screen, theme, Entity::Junk, junk.pos_x, junk.pos_y);
     }
 
     // Draw the player
-    move_cursor_padded(player.pos_x, player.pos_y);
     if player.is_alive {
-        print!("@")
+        draw_entity(screen, theme, Entity::Player, player.pos_x, player.pos_y);
     } else {
-        print!("%");
+        draw_entity(
+            screen,
+            theme,
+            Entity::DeadPlayer,
+            player.pos_x,
+            player.pos_y,
+        );
     }
 
-    execute!(
-        io::stdout(),
-        MoveTo(BOARD_WIDTH as u16 + 4, BOARD_HEIGHT as u16 + 4)
-    )
-    .unwrap();
+    // Point out what caught the player, in the colours of the culprit
+    if let Some(death) = death {
+        let entity = if (death.pos_x, death.pos_y) == (player.pos_x, player.pos_y) {
+            Entity::DeadPlayer
+        } else {
+            match death.killer {
+                Killer::Robot(kind) => Entity::Robot(kind),
+                Killer::JunkHeap => Entity::Junk,
+            }
+        };
+        screen.print_styled(
+            death.pos_x + PADDING_LEFT,
+            death.pos_y + PADDING_TOP,
+            &theme.glyph(entity).to_string(),
+            theme.style(Entity::Culprit),
+        );
+    }
 }
 
-fn alive_robots(robots: &Vec<DumbRobot>) -> i32 {
-    let mut alive = 0;
-    for robot in robots {
-        if !robot.is_scrap {
-            alive += 1;
+/// The message at the top of the board once the player is caught.
+pub fn death_message(death: Option<&Death>) -> String {
+    let Some(death) = death else {
+        return "[You did not make it. You were caught by the robots..]".to_string();
+    };
+    match death.killer {
+        Killer::Robot(kind) => {
+            let name = kind.name();
+            if death.teleported {
+                format!("[You teleported right into the reach of a {}..]", name)
+            } else {
+                format!("[You did not make it. You were caught by a {}..]", name)
+            }
         }
+        Killer::JunkHeap if death.teleported => "[You teleported onto a junk heap..]".to_string(),
+        Killer::JunkHeap => "[You did not make it. You were buried under junk..]".to_string(),
     }
-    alive
 }
 
-// A very busy redraw function. However. This is the final version!
-pub fn draw_boundaries(
-    player: &Player, gamestate: &GameState, junk_heaps: &Vec<JunkHeap>,
-    dumb_robots: &Vec<DumbRobot>,
-) {
-    execute!(io::stdout(), Clear(ClearType::All)).expect("Failed to clear screen");
-    execute!(io::stdout(), MoveTo(0, 0)).expect("Failed to move cursor");
+// The compass of movement keys at the top of the side panel. With single character keys it is
+//
+// Directions:  y k u
+//               \|/
+//              h- -l
+//               /|\
+//              b j n
+//
+// and longer key names widen their column.
+fn direction_lines(keymap: &Keymap) -> [String; 5] {
+    let key = |dx: i32, dy: i32| keymap.label(Action::Move(dx, dy));
+    let column_width = |dx: i32| {
+        (-1..=1)
+            .filter(|dy| (dx, *dy) != (0, 0))
+            .map(|dy| key(dx, dy).chars().count())
+            .max()
+            .unwrap_or(1)
+    };
+    let (left, middle) = (column_width(-1), column_width(0));
+    let row = |prefix: &str, dy: i32| {
+        format!(
+            "{:<13}{:>left$} {:^middle$} {}",
+            prefix,
+            key(-1, dy),
+            key(0, dy),
+            key(1, dy)
+        )
+    };
+    let lines = |left_line: &str, right_line: &str| {
+        format!(
+            "{:<w$}{}{:^middle$}{}",
+            "",
+            left_line,
+            "|",
+            right_line,
+            w = 13 + left
+        )
+    };
+    [
+        row("Directions:", -1),
+        lines("\\", "/"),
+        format!(
+            "{:<13}{:>left$}-{:middle$}-{}",
+            "",
+            key(-1, 0),
+            "",
+            key(1, 0)
+        ),
+        lines("/", "\\"),
+        row("", 1),
+    ]
+}
+
+fn alive_robots(robots: &[DumbRobot]) -> usize {
+    robots.iter().filter(|robot| !robot.is_scrap).count()
+}
+
+// A very busy redraw function. However. This is the final version! It starts a new frame on
+// the screen, which is only shown once everything on top of it has been drawn as well.
+pub fn draw_boundaries(screen: &mut Screen, theme: &Theme, keymap: &Keymap, engine: &Engine) {
+    let size = engine.size;
+    let player = &engine.player;
+    let gamestate = &engine.state;
+    let junk_heaps = &engine.junk_heaps;
+    let dumb_robots = &engine.robots;
+
+    screen.clear();
     let score_str = format!("Score:  {}", player.score);
-    let safe_teleports_str = format!("s:  safe teleport ({})", player.safe_teleports);
     let level_str = format!("Level:  {}", gamestate.level);
-    let bomb_str = format!("a:  bomb ({})", player.bombs);
-    let player_str;
-    if player.invincible {
-        player_str = format!("@:  you (invincible)");
+    let command = |action: Action, text: &str| format!("{}:  {}", keymap.label(action), text);
+    let safe_teleports_str = command(
+        Action::SafeTeleport,
+        &format!("safe teleport ({})", player.safe_teleports),
+    );
+    let bomb_str = command(Action::Bomb, &format!("bomb ({})", player.bombs));
+    let wait_for_end_str = command(Action::WaitForEnd, "wait for end");
+    let teleport_str = command(Action::Teleport, "teleport (unsafe)");
+    let wait_str = command(Action::Wait, "wait one turn");
+    let quit_str = command(Action::Quit, "quit");
+    let legend = |entity: Entity, text: &str| format!("{}:  {}", theme.glyph(entity), text);
+    let player_str = if player.invincible {
+        legend(Entity::Player, "you (invincible)")
     } else {
-        player_str = format!("@:  you");
-    }
+        legend(Entity::Player, "you")
+    };
+    // Only the kinds of robots this level has, and damaged robots along with the armoured ones
+    let robot_strs: Vec<String> = RobotKind::ALL
+        .into_iter()
+        .filter(|kind| {
+            dumb_robots
+                .iter()
+                .any(|robot| robot.kind == *kind || (kind.armoured() && robot.damaged))
+        })
+        .map(|kind| {
+            let line = legend(Entity::Robot(kind), kind.name());
+            if kind.armoured() {
+                format!("{} ({}: damaged)", line, theme.glyph(Entity::DamagedRobot))
+            } else {
+                line
+            }
+        })
+        .collect();
+    let junk_str = format!("{}  {}", legend(Entity::Junk, "junk heap"), player_str);
     let alive_robots_str = format!("Robots:  {}", alive_robots(dumb_robots));
     let junk_piles_str = format!("Junk piles:  {}", junk_heaps.len());
-    let menu = vec![
-        "Directions:  y k u",
-        "              \\|/",
-        "             h- -l",
-        "              /|\\",
-        "             b j n",
+    let directions = direction_lines(keymap);
+    let mut menu = vec![
+        directions[0].as_str(),
+        directions[1].as_str(),
+        directions[2].as_str(),
+        directions[3].as_str(),
+        directions[4].as_str(),
         "Commands:",
-        "",
-        "w:  wait for end",
-        "t:  teleport (unsafe)",
-        safe_teleports_str.as_str(),
-        bomb_str.as_str(),
-        ".:  wait one turn",
-        "q:  quit",
-        "",
-        "Legend:",
-        "",
-        "+:  robot",
-        "&:  super robot",
-        "N:  killer robot",
-        "#:  junk heap",
-        player_str.as_str(),
-        "",
-        "",
-        "",
-        score_str.as_str(),
+        wait_for_end_str.as_str(),
+        teleport_str.as_str(),
     ];
+    // Classic games have no safe teleports or bombs
+    if !engine.classic {
+        menu.extend([safe_teleports_str.as_str(), bomb_str.as_str()]);
+    }
+    menu.extend([wait_str.as_str(), quit_str.as_str(), "", "Legend:"]);
+    menu.extend(robot_strs.iter().map(String::as_str));
+    menu.push(junk_str.as_str());
+    // Above the lines at the bottom of the panel
+    menu.truncate((panel_bottom(size) - 3) as usize);
     for (line, i) in menu.iter().zip(0..) {
-        move_cursor_padded(BOARD_WIDTH + 4, i);
-        print!("{}", line);
+        print_padded(screen, size.width + 4, i, line);
     }
+    print_padded(screen, size.width + 4, panel_bottom(size), &score_str);
 
-    execute!(io::stdout(), MoveTo(0, 0)).expect("Failed to move cursor");
-
-    print!("{}", "\n".repeat(PADDING_TOP as usize));
-    print!("{}", " ".repeat(PADDING_LEFT as usize));
-    print!("/");
-    print!("{}", "-".repeat(BOARD_WIDTH as usize));
-    println!("\\");
-    for _ in 0..24 {
-        print!("{}", " ".repeat(PADDING_LEFT as usize));
-        print!("|");
-        print!("{}", " ".repeat(BOARD_WIDTH as usize));
-        println!("|");
+    let border = &theme.border;
+    let horizontal = border.horizontal.to_string().repeat(size.width as usize);
+    let top = format!("{}{}{}", border.top_left, horizontal, border.top_right);
+    print_padded(screen, 0, 0, &top);
+    for y in 1..=size.height {
+        print_padded(screen, 0, y, &border.vertical.to_string());
+        print_padded(screen, size.width + 1, y, &border.vertical.to_string());
     }
-    print!("{}", " ".repeat(PADDING_LEFT as usize));
-    print!("\\");
-    print!("{}", "-".repeat(BOARD_WIDTH as usize));
-    println!("/");
-    println!(
+    let bottom = format!(
+        "{}{}{}",
+        border.bottom_left, horizontal, border.bottom_right
+    );
+    print_padded(screen, 0, size.height + 1, &bottom);
+    // Below the side menu as well, when the board is not as high as the menu
+    let stats = format!(
         "\t{}\t  {}\t{}",
         level_str, junk_piles_str, alive_robots_str
     );
+    screen.print(0, PADDING_TOP + panel_bottom(size) + 2, &stats);
+    if engine.practice {
+        draw_panel_line(screen, size, 1, &command(Action::Undo, "undo (practice)"));
+    }
+}
+
+// Shows what happened during the last turn, on top of the board that was drawn before it
+pub fn draw_events(screen: &mut Screen, theme: &Theme, size: BoardSize, events: &[GameEvent]) {
+    for event in events {
+        match event {
+            GameEvent::Teleported { safe } => {
+                // Because Andreas said so.. We need a prompt to tell people that they are teleporting..
+                if *safe {
+                    draw_panel_line(screen, size, 0, "Teleporting (safe)...");
+                } else {
+                    draw_panel_line(screen, size, 0, "Teleporting...");
+                }
+                screen.present();
+
+                // Sleep for 500ms
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+            GameEvent::BombExploded { cells } => {
+                // Show the blast on every position the bomb covered
+                for (x, y) in cells {
+                    draw_entity(screen, theme, Entity::Explosion, *x, *y);
+                }
+                screen.present();
+                // Wait for 500 ms
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Draws the summary of a cleared level in a box on the board, with the prompt (if any) below it.
+pub fn draw_level_summary(
+    screen: &mut Screen, theme: &Theme, engine: &Engine, summary: &GameEvent, prompt: &str,
+) {
+    let GameEvent::LevelCleared {
+        level,
+        bonus,
+        stats,
+        safe_teleports,
+    } = summary
+    else {
+        return;
+    };
+
+    let mut lines = vec![format!("Level {} complete!", level), String::new()];
+    for (kind, scrapped) in RobotKind::ALL.iter().zip(stats.scrapped) {
+        if scrapped > 0 || *kind == RobotKind::Robot {
+            let entity = Entity::Robot(*kind);
+            lines.push(format!(
+                "{} {}s:  {}",
+                theme.glyph(entity),
+                kind.name(),
+                scrapped
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.push(format!("Collisions:  {}", stats.collisions));
+    if !engine.classic {
+        lines.push(format!("Bombs:  {}", stats.bombed));
+    }
+    lines.push(format!("Junk heaps:  {}", stats.junked));
+    lines.push(String::new());
+    lines.push(format!("Turns taken:  {}", stats.turns));
+    lines.push(format!("Teleports used:  {}", stats.teleports));
+    lines.push(format!("Wait bonus:  {}", bonus));
+    if !engine.classic {
+        lines.push(format!("Safe teleports:  {} (+1)", safe_teleports));
+    }
+    if !prompt.is_empty() {
+        lines.push(String::new());
+        lines.push(prompt.to_string());
+    }
+
+    // In the middle of the board, as far as it fits
+    let inner_width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as i32
+        + 2;
+    let height = lines.len() as i32 + 2;
+    let x = ((engine.size.width - inner_width) / 2).max(0);
+    let y = ((engine.size.height - height) / 2 + 1).max(0);
+
+    let border = &theme.border;
+    let horizontal = border.horizontal.to_string().repeat(inner_width as usize);
+    let top = format!("{}{}{}", border.top_left, horizontal, border.top_right);
+    print_padded(screen, x, y, &top);
+    for (line, i) in lines.iter().zip(1..) {
+        let text = format!(
+            "{} {:<width$} {}",
+            border.vertical,
+            line,
+            border.vertical,
+            width = inner_width as usize - 2
+        );
+        print_padded(screen, x, y + i, &text);
+    }
+    let bottom = format!(
+        "{}{}{}",
+        border.bottom_left, horizontal, border.bottom_right
+    );
+    print_padded(screen, x, y + height - 1, &bottom);
+}
+
+// Shows the progress of a replay, and the keys to control it, below the legend
+pub fn draw_replay_status(
+    screen: &mut Screen, size: BoardSize, played: usize, actions: usize, delay: u64, paused: bool,
+) {
+    let status = if paused {
+        format!("Replay:  {}/{} (paused)", played, actions)
+    } else {
+        format!("Replay:  {}/{} ({}ms)", played, actions, delay)
+    };
+    draw_panel_line(screen, size, 3, &status);
+    draw_panel_line(screen, size, 2, "space: pause  n: step  +/-: speed");
 }
 
 pub fn splash_screen() {
@@ -212,53 +527,45 @@ pub fn splash_screen() {
     disable_raw_mode().expect("Failed to disable raw mode");
 }
 
-// Retry function takes either a y/n input and returns a boolean
-pub fn retry_query() -> bool {
-    execute!(
-        io::stdout(),
-        MoveTo(
-            BOARD_WIDTH as u16 + 7,
-            PADDING_TOP as u16 + BOARD_HEIGHT as u16 - 2
-        )
-    )
-    .unwrap();
-    print!("Do you want to try again? (y/n) ");
+/// What the player wants to do after being caught.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Retry {
+    Yes,
+    No,
+    /// Take back the move that got the player caught, in practice mode
+    Undo,
+}
+
+// Retry function takes either a y/n input, or the undo key when there is something to undo, or
+// returns None if the question has to be asked again, for instance because the terminal was
+// resized and needs a redraw
+pub fn retry_query(
+    screen: &mut Screen, size: BoardSize, keymap: &Keymap, can_undo: bool,
+) -> Option<Retry> {
+    draw_panel_line(screen, size, 2, "Do you want to try again? (y/n) ");
+    screen.present();
 
     // Sleep for a 1000ms
 
-    let mut try_again = false;
-    io::stdout().flush().unwrap();
+    let mut try_again = None;
 
     enable_raw_mode().expect("Failed to enable raw mode");
 
-    match read().expect("Failed to read event") {
-        Event::Key(event) => {
-            match event.code {
-                KeyCode::Char(c) => {
-                    match c {
-                        'y' => try_again = true,  // We want to retry
-                        'Y' => try_again = true, // We want to retry (should caps lock be initiated)
-                        'n' => try_again = false, // We do not want to retry
-                        'N' => try_again = false, // We do not want to retry (should caps lock be initiated)
-                        _ => {
-                            try_again = false;
-                            retry_query();
-                        }
-                    }
-                }
+    if let Event::Key(event) = read().expect("Failed to read event") {
+        if can_undo && keymap.action(event.code) == Some(Action::Undo) {
+            try_again = Some(Retry::Undo);
+        } else if let KeyCode::Char(c) = event.code {
+            match c {
+                'y' => try_again = Some(Retry::Yes), // We want to retry
+                'Y' => try_again = Some(Retry::Yes), // We want to retry (should caps lock be initiated)
+                'n' => try_again = Some(Retry::No),  // We do not want to retry
+                'N' => try_again = Some(Retry::No), // We do not want to retry (should caps lock be initiated)
                 _ => (),
             }
         }
-        _ => try_again = false,
     }
 
     disable_raw_mode().expect("Failed to disable raw mode");
 
-    execute!(
-        io::stdout(),
-        MoveTo(BOARD_WIDTH as u16 + 4, BOARD_HEIGHT as u16 + 4)
-    )
-    .unwrap();
-
     try_again
 }
@@ -0,0 +1,1247 @@
+/// This module contains the rules of the game, free of any terminal handling.
+///
+/// The `Engine` owns the complete game: the player, the robots, the junk heaps, the level item
+/// and the game state. Every random decision is taken from a single RNG seeded at the start, so
+/// the same seed and the same actions always play out the same game. It is driven by feeding it
+/// `Action`s through `Engine::apply` (or `Engine::tick` while waiting for the end of a level),
+/// and it reports what happened as a list of `GameEvent`s. Drawing, sleeping and reading keys is
+/// left to the consumer of those events.
+///
+/// All robots move at the same time: each one picks its destination from the board as it was at
+/// the start of the turn, and only then are captures, junk and collisions resolved, so the outcome
+/// never depends on the order the robots are stored in. Two robots that swap places pass each
+/// other; robots that end up in the same cell crash.
+///
+/// In classic mode the engine plays by the rules of BSD robots instead: there are only plain
+/// robots, every scrapped robot is worth `ROBOT_SCORE` points, there are no safe teleports,
+/// bombs or items, and teleporting lands on any free cell, safe or not.
+///
+/// Robots that are scrapped while the player waits for the end of the level earn a bonus once
+/// the level is cleared, as a reward for the risk of not being able to run anymore.
+///
+/// In practice mode the engine keeps a snapshot of the whole game before every action, so
+/// `Action::Undo` can take the last turn back, random number generator included.
+use crate::rng::GameRng;
+use crate::robot_kind::{RobotKind, TELEPORT_INTERVAL, TELEPORT_RANGE};
+use crate::structs::*;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The points a scrapped robot is worth in classic mode, like in BSD robots.
+pub const ROBOT_SCORE: i32 = 10;
+
+/// The extra points for every robot that is scrapped while the player waits for the end.
+pub const WAIT_BONUS: i32 = 1;
+
+/// Something the player can do on their turn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Move one step in the given direction
+    Move(i32, i32),
+    /// Teleport to a random position
+    Teleport,
+    /// Teleport to a position away from the robots, if any safe teleports are left
+    SafeTeleport,
+    /// Drop a bomb around the player
+    Bomb,
+    /// Let the robots move until the level is over or the player is dead
+    WaitForEnd,
+    /// Wait for one turn
+    Wait,
+    /// Take back the last action, in practice mode
+    Undo,
+    /// Give up the game
+    Quit,
+}
+
+/// Something that happened while the engine processed an action.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    /// The move was not possible, no turn was taken
+    Blocked,
+    /// The player teleported
+    Teleported { safe: bool },
+    /// A bomb went off and covered these cells
+    BombExploded { cells: Vec<(i32, i32)> },
+    /// The level item became visible
+    ItemRevealed,
+    /// The player picked up the level item
+    ItemPickedUp { kind: i32 },
+    /// The player was caught
+    PlayerDied,
+    /// All robots were scrapped, the next level has been generated. The bonus is what the player
+    /// earned by waiting for the end of the level, and has been added to the score. The stats
+    /// tell what happened during the level, and the safe teleports are what the player has for
+    /// the next one.
+    LevelCleared {
+        level: i32,
+        bonus: i32,
+        stats: LevelStats,
+        safe_teleports: i32,
+    },
+    /// The last action was taken back
+    Undone,
+    /// The player gave up
+    Quit,
+}
+
+/// What caught the player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Killer {
+    Robot(RobotKind),
+    JunkHeap,
+}
+
+/// How the player died, and where the killer was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Death {
+    pub killer: Killer,
+    pub pos_x: i32,
+    pub pos_y: i32,
+    /// Whether the player teleported into it
+    pub teleported: bool,
+}
+
+pub struct Engine {
+    pub player: Player,
+    pub robots: Vec<DumbRobot>,
+    pub junk_heaps: Vec<JunkHeap>,
+    pub item: Item,
+    pub state: GameState,
+    pub level_stats: LevelStats,
+    /// How the player died, once they have
+    pub death: Option<Death>,
+    pub seed: u64,
+    pub size: BoardSize,
+    /// What occupies each cell: 0 for free, 1 for a robot and 2 for a junk heap
+    pub board: Vec<Vec<i32>>,
+    pub rng: GameRng,
+    /// Whether the game follows the rules of BSD robots
+    pub classic: bool,
+    /// The first level with teleporting robots
+    pub teleporting_robot_level: i32,
+    /// Whether actions can be undone, which keeps the game out of the highscores
+    pub practice: bool,
+    /// The game as it was before each action, most recent last. Only kept in practice mode.
+    pub history: Vec<Snapshot>,
+}
+
+/// Everything an action can change.
+pub struct Snapshot {
+    player: Player,
+    robots: Vec<DumbRobot>,
+    junk_heaps: Vec<JunkHeap>,
+    item: Item,
+    state: GameState,
+    level_stats: LevelStats,
+    death: Option<Death>,
+    board: Vec<Vec<i32>>,
+    rng: GameRng,
+}
+
+impl Engine {
+    /// Creates a new game for the given player, seed and board size, and generates the first level.
+    pub fn new(
+        username: &str, seed: u64, size: BoardSize, classic: bool, teleporting_robot_level: i32,
+    ) -> Engine {
+        let mut engine = Engine {
+            player: Player {
+                username: username.to_string(),
+                score: 0,
+                is_alive: true,
+                pos_x: 0,
+                pos_y: 0,
+                safe_teleports: if classic { 0 } else { 2 },
+                invincible: false,
+                bombs: 0,
+            },
+            robots: Vec::new(),
+            junk_heaps: Vec::new(),
+            item: Item {
+                pos_x: 0,
+                pos_y: 0,
+                level: 0,
+                kind: 0,
+                visible: false,
+                picked_up: false,
+            },
+            state: GameState {
+                turn: 0,
+                level: 1,
+                wait_for_end: false,
+                bomb_away: false,
+                robots_destroyed: 0,
+                bombs_used: 0,
+                teleports_used: 0,
+                scrapped_while_waiting: 0,
+            },
+            level_stats: LevelStats::default(),
+            death: None,
+            seed,
+            size,
+            board: vec![vec![0; size.width as usize]; size.height as usize],
+            rng: GameRng::new(seed),
+            classic,
+            teleporting_robot_level,
+            practice: false,
+            history: Vec::new(),
+        };
+        engine.generate_level();
+        engine
+    }
+
+    /// Performs the players action and, if it was a legal one, lets the robots move.
+    pub fn apply(&mut self, action: Action) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        if action == Action::Undo {
+            events.push(self.undo());
+            return events;
+        }
+        // Only turns that are played count, and can be taken back, not moves that were blocked
+        let snapshot = (self.practice && action != Action::Quit).then(|| self.snapshot());
+
+        let legal_move = match action {
+            Action::Move(d_pos_x, d_pos_y) => self.move_player(d_pos_x, d_pos_y),
+            Action::Teleport => {
+                events.push(self.teleport_player(false));
+                true
+            }
+            Action::SafeTeleport | Action::Bomb if self.classic => false,
+            Action::SafeTeleport => {
+                events.push(self.teleport_player(true));
+                true
+            }
+            Action::Bomb if self.player.bombs <= 0 => false,
+            Action::Bomb => {
+                self.state.bomb_away = true;
+                true
+            }
+            Action::WaitForEnd => {
+                self.state.wait_for_end = true;
+                true
+            }
+            Action::Wait => true,
+            Action::Undo => unreachable!("Undo is handled before the turn starts"),
+            Action::Quit => {
+                self.player.is_alive = false;
+                events.push(GameEvent::Quit);
+                return events;
+            }
+        };
+
+        if legal_move {
+            self.history.extend(snapshot);
+            events.extend(self.tick());
+            if let Some(death) = &mut self.death {
+                death.teleported = matches!(action, Action::Teleport | Action::SafeTeleport);
+            }
+        } else {
+            events.push(GameEvent::Blocked);
+        }
+        events
+    }
+
+    /// Lets the robots move one step without any action from the player.
+    pub fn tick(&mut self) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        // The final move of the robots, once the player was caught, is no turn of the game
+        if self.player.is_alive {
+            self.state.turn += 1;
+            self.level_stats.turns += 1;
+        }
+        let was_alive = self.player.is_alive;
+        let scrap_before = self.scrapped_robots();
+        let score_before = self.player.score;
+
+        self.game_tick(&mut events);
+        let scrapped = self.scrapped_robots() - scrap_before;
+        self.state.robots_destroyed += scrapped;
+        if self.state.wait_for_end {
+            self.state.scrapped_while_waiting += scrapped;
+        }
+        if self.classic {
+            // Every robot is worth the same, no matter how it was scrapped
+            self.player.score = score_before + scrapped * ROBOT_SCORE;
+        }
+
+        if was_alive && !self.player.is_alive {
+            events.push(GameEvent::PlayerDied);
+        }
+
+        if self.player.is_alive && !self.any_robots_left() {
+            let bonus = self.wait_bonus();
+            self.player.score += bonus;
+            let stats = self.level_stats;
+            self.state.wait_for_end = false;
+            self.state.scrapped_while_waiting = 0;
+            self.state.level += 1;
+            self.generate_level();
+            events.push(GameEvent::LevelCleared {
+                level: self.state.level - 1,
+                bonus,
+                stats,
+                safe_teleports: self.player.safe_teleports,
+            });
+        }
+        events
+    }
+
+    // The extra points for the robots that were scrapped while the player waited for the end of
+    // the level. In classic mode that makes every one of them count twice, like in BSD robots.
+    fn wait_bonus(&self) -> i32 {
+        if !self.state.wait_for_end {
+            return 0;
+        }
+        let per_robot = if self.classic {
+            ROBOT_SCORE
+        } else {
+            WAIT_BONUS
+        };
+        self.state.scrapped_while_waiting * per_robot
+    }
+
+    /// The mode of the game, which keeps its highscore apart from the other modes.
+    pub fn mode(&self) -> GameMode {
+        match (self.classic, self.practice) {
+            (false, false) => GameMode::Normal,
+            (false, true) => GameMode::Practice,
+            (true, false) => GameMode::Classic,
+            (true, true) => GameMode::ClassicPractice,
+        }
+    }
+
+    /// Whether there is an action to take back.
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            player: self.player.clone(),
+            robots: self.robots.clone(),
+            junk_heaps: self.junk_heaps.clone(),
+            item: self.item.clone(),
+            state: self.state,
+            level_stats: self.level_stats,
+            death: self.death,
+            board: self.board.clone(),
+            rng: self.rng.clone(),
+        }
+    }
+
+    // Puts the game back to how it was before the last action, even if the player died of it
+    fn undo(&mut self) -> GameEvent {
+        let Some(snapshot) = self.history.pop() else {
+            return GameEvent::Blocked;
+        };
+        self.player = snapshot.player;
+        self.robots = snapshot.robots;
+        self.junk_heaps = snapshot.junk_heaps;
+        self.item = snapshot.item;
+        self.state = snapshot.state;
+        self.level_stats = snapshot.level_stats;
+        self.death = snapshot.death;
+        self.board = snapshot.board;
+        self.rng = snapshot.rng;
+        GameEvent::Undone
+    }
+
+    pub fn any_robots_left(&self) -> bool {
+        self.robots.iter().any(|robot| !robot.is_scrap)
+    }
+
+    fn scrapped_robots(&self) -> i32 {
+        self.robots.iter().filter(|robot| robot.is_scrap).count() as i32
+    }
+
+    fn move_player(&mut self, d_pos_x: i32, d_pos_y: i32) -> bool {
+        let player = &mut self.player;
+        player.pos_x = (player.pos_x + d_pos_x).clamp(1, self.size.width);
+        player.pos_y = (player.pos_y + d_pos_y).clamp(1, self.size.height);
+
+        if self.board[player.pos_y as usize - 1][player.pos_x as usize - 1] != 0 {
+            player.pos_x -= d_pos_x;
+            player.pos_y -= d_pos_y;
+            return false;
+        }
+
+        true
+    }
+
+    fn game_tick(&mut self, events: &mut Vec<GameEvent>) {
+        let player = &mut self.player;
+        let dumb_robots = &mut self.robots;
+        let junk_heaps = &mut self.junk_heaps;
+        let game_board_data = &mut self.board;
+        let item = &mut self.item;
+        let game_state = &mut self.state;
+        let rng = &mut self.rng;
+        let level_stats = &mut self.level_stats;
+        let death = &mut self.death;
+        let size = self.size;
+
+        let classic = self.classic;
+
+        // Check if we should bomb away
+        if game_state.bomb_away {
+            game_state.bomb_away = false;
+            player.bombs -= 1;
+            game_state.bombs_used += 1;
+            // Here we should create a vector with all coordinates based on the player position in the following form
+            // ..B..   Space two steps up
+            // .BBB.   Space diagonal up and left, up and right, and up
+            // BB@BB   Two spaces left, one space left, two spaces right, one space right
+            // .BBB.   Space diagonal down and left, down and right, and down
+            // ..B..   Space two steps down
+            let bomb_coordinates = vec![
+                (player.pos_x, player.pos_y - 2),
+                (player.pos_x - 1, player.pos_y - 1),
+                (player.pos_x + 1, player.pos_y - 1),
+                (player.pos_x, player.pos_y - 1),
+                (player.pos_x - 2, player.pos_y),
+                (player.pos_x - 1, player.pos_y),
+                (player.pos_x + 1, player.pos_y),
+                (player.pos_x + 2, player.pos_y),
+                (player.pos_x - 1, player.pos_y + 1),
+                (player.pos_x + 1, player.pos_y + 1),
+                (player.pos_x, player.pos_y + 1),
+                (player.pos_x, player.pos_y + 2),
+            ];
+
+            // For all these coordinates, as long as they are in the playing field, scrap the robots
+            let mut cells = Vec::new();
+            for coordinate in bomb_coordinates {
+                if size.contains(coordinate.0, coordinate.1) {
+                    // Check if there is a robot at this position
+                    for robot in dumb_robots.iter_mut() {
+                        if robot.pos_x == coordinate.0 && robot.pos_y == coordinate.1 {
+                            scrap(robot, level_stats, Scrap::Bomb);
+                            player.score += robot.kind.score();
+
+                            // Add a junk heap the heaps array
+                            junk_heaps.push(JunkHeap {
+                                pos_x: robot.pos_x,
+                                pos_y: robot.pos_y,
+                            });
+                        }
+                    }
+                    cells.push(coordinate);
+                }
+            }
+            events.push(GameEvent::BombExploded { cells });
+        }
+
+        // The robots decide where to go from the board as it was at the start of the turn, with
+        // the junk heaps the bomb just left
+        for junk in junk_heaps.iter() {
+            game_board_data[junk.pos_y as usize - 1][junk.pos_x as usize - 1] = 2;
+        }
+
+        // All robots move at the same time. First every robot decides where it goes, based on
+        // the board as it was at the start of the turn, in the way its kind moves.
+        let player_cell = (player.pos_x, player.pos_y);
+
+        // Every few turns some robots teleport instead of moving. They pick their spots from the
+        // top of the board down, so the random numbers they draw don't depend on the order the
+        // robots are stored in.
+        let mut teleporters: Vec<usize> = (0..dumb_robots.len())
+            .filter(|index| {
+                let robot = &dumb_robots[*index];
+                !robot.is_scrap
+                    && robot.kind.teleports()
+                    && game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] != 2
+            })
+            .collect();
+        teleporters.sort_by_key(|index| (dumb_robots[*index].pos_y, dumb_robots[*index].pos_x));
+        let mut teleports = HashMap::new();
+        for index in teleporters {
+            let robot = &mut dumb_robots[index];
+            robot.turns += 1;
+            if robot.turns >= TELEPORT_INTERVAL {
+                robot.turns = 0;
+                if let Some(to) = teleport_destination(rng, game_board_data, size, player_cell) {
+                    teleports.insert(index, to);
+                }
+            }
+        }
+
+        let mut moves: Vec<(usize, (i32, i32))> = Vec::new();
+        let mut pushes: Vec<((i32, i32), (i32, i32))> = Vec::new();
+        for (index, robot) in dumb_robots.iter_mut().enumerate() {
+            if robot.is_scrap {
+                continue;
+            }
+            let kind = robot.kind;
+
+            // First just make sure that this robot is not standing on a junk pile.
+            if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
+                scrap(robot, level_stats, Scrap::Junk);
+                player.score += kind.score();
+                continue;
+            }
+
+            if let Some(to) = teleports.get(&index) {
+                moves.push((index, *to));
+                continue;
+            }
+
+            let from = (robot.pos_x, robot.pos_y);
+            let nearby = (from.0 - player.pos_x).abs() <= 1 && (from.1 - player.pos_y).abs() <= 1;
+            let mut to = if kind.catches_nearby() && nearby {
+                // Some robots don't have to move to catch the player, and once they have, they
+                // stay where they are
+                if player.is_alive {
+                    player_cell
+                } else {
+                    from
+                }
+            } else {
+                // A robot stops at the first junk heap or at the player, whatever comes first
+                let path = kind.path(from, player_cell, size);
+                path.iter()
+                    .find(|(x, y)| {
+                        (*x, *y) == player_cell
+                            || game_board_data[*y as usize - 1][*x as usize - 1] == 2
+                    })
+                    .or(path.last())
+                    .copied()
+                    .unwrap_or(from)
+            };
+
+            // Some robots push the junk heap they run into one cell further. They can't push it
+            // off the board, so then they stay where they are.
+            if kind.pushes_junk() && game_board_data[to.1 as usize - 1][to.0 as usize - 1] == 2 {
+                let behind = (2 * to.0 - from.0, 2 * to.1 - from.1);
+                if size.contains(behind.0, behind.1) {
+                    pushes.push((to, behind));
+                } else {
+                    to = from;
+                }
+            }
+            moves.push((index, to));
+        }
+
+        // Then the robots that reach the player catch them, all at the same time. An invincible
+        // player wrecks every one of them before they get there.
+        let catchers: Vec<usize> = moves
+            .iter()
+            .filter(|(_, to)| *to == player_cell)
+            .map(|(index, _)| *index)
+            .collect();
+        if player.invincible && !catchers.is_empty() {
+            for index in &catchers {
+                break_down(&mut dumb_robots[*index], player, junk_heaps, level_stats);
+            }
+            moves.retain(|(index, _)| !catchers.contains(index));
+        } else if !catchers.is_empty() {
+            player.is_alive = false;
+            // Some robots don't have to move to catch the player, they stay where they are
+            for (index, to) in moves.iter_mut() {
+                let robot = &dumb_robots[*index];
+                if *to == player_cell && robot.kind.catches_nearby() {
+                    *to = (robot.pos_x, robot.pos_y);
+                }
+            }
+            // When more than one robot gets the player, the kind with the lowest id takes the
+            // blame, and then the one nearest to the top left
+            let killer = catchers
+                .iter()
+                .map(|index| &dumb_robots[*index])
+                .min_by_key(|robot| (robot.kind.id(), robot.pos_y, robot.pos_x))
+                .unwrap();
+            if killer.kind.catches_nearby() {
+                caught(
+                    death,
+                    Killer::Robot(killer.kind),
+                    killer.pos_x,
+                    killer.pos_y,
+                );
+            } else {
+                caught(
+                    death,
+                    Killer::Robot(killer.kind),
+                    player.pos_x,
+                    player.pos_y,
+                );
+            }
+        }
+
+        // The pushed junk heaps move before the robots do, so whatever ends up behind them is
+        // buried. A heap that is pushed from two sides doesn't move, and heaps that are pushed
+        // onto another one become one heap.
+        let pushed: Vec<_> = pushes
+            .iter()
+            .filter(|(from, _)| pushes.iter().filter(|(other, _)| other == from).count() == 1)
+            .filter_map(|(from, to)| {
+                let heap = junk_heaps
+                    .iter()
+                    .position(|junk| (junk.pos_x, junk.pos_y) == *from)?;
+                Some((heap, *from, *to))
+            })
+            .collect();
+        for (heap, from, to) in pushed {
+            (junk_heaps[heap].pos_x, junk_heaps[heap].pos_y) = to;
+            game_board_data[from.1 as usize - 1][from.0 as usize - 1] = 0;
+        }
+        let mut heaps = HashSet::new();
+        junk_heaps.retain(|junk| heaps.insert((junk.pos_x, junk.pos_y)));
+        for junk in junk_heaps.iter() {
+            game_board_data[junk.pos_y as usize - 1][junk.pos_x as usize - 1] = 2;
+        }
+
+        // Then all of them move
+        let mut origins = Vec::new();
+        for (index, (x, y)) in &moves {
+            let robot = &mut dumb_robots[*index];
+            origins.push((*index, (robot.pos_x, robot.pos_y)));
+            robot.pos_x = *x;
+            robot.pos_y = *y;
+        }
+
+        // Armoured robots that run into junk or into each other lose their armour, and bounce
+        // back to where they came from. There they may crash into a robot that moved in, so this
+        // goes on until no robot bounces anymore.
+        loop {
+            let robots_per_cell = count_robots(dumb_robots);
+            let mut bounced = false;
+            for (index, origin) in &origins {
+                let robot = &mut dumb_robots[*index];
+                let cell = (robot.pos_x, robot.pos_y);
+                let crashed = game_board_data[cell.1 as usize - 1][cell.0 as usize - 1] == 2
+                    || robots_per_cell[&cell] > 1;
+                if robot.is_scrap || !robot.kind.armoured() || !crashed || cell == *origin {
+                    continue;
+                }
+                robot.kind = RobotKind::Robot;
+                robot.damaged = true;
+                (robot.pos_x, robot.pos_y) = *origin;
+                bounced = true;
+            }
+            if !bounced {
+                break;
+            }
+        }
+
+        // The other robots that run into junk turn into scrap
+        for robot in dumb_robots.iter_mut().filter(|robot| !robot.is_scrap) {
+            if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
+                scrap(robot, level_stats, Scrap::Junk);
+                player.score += robot.kind.score();
+            }
+        }
+
+        // And the robots that end up in the same cell crash into each other, leaving one heap of
+        // junk behind
+        let robots_per_cell = count_robots(dumb_robots);
+        for robot in dumb_robots.iter_mut() {
+            let cell = (robot.pos_x, robot.pos_y);
+            if robot.is_scrap || robots_per_cell[&cell] < 2 {
+                continue;
+            }
+            scrap(robot, level_stats, Scrap::Collision);
+            player.score += 2 * robot.kind.score();
+            if !junk_heaps
+                .iter()
+                .any(|junk| (junk.pos_x, junk.pos_y) == cell)
+            {
+                junk_heaps.push(JunkHeap {
+                    pos_x: cell.0,
+                    pos_y: cell.1,
+                });
+            }
+        }
+
+        // Put everything on the board where it ended up
+        clear_board(game_board_data);
+        for junk in junk_heaps.iter() {
+            game_board_data[junk.pos_y as usize - 1][junk.pos_x as usize - 1] = 2;
+        }
+        for robot in dumb_robots.iter().filter(|robot| !robot.is_scrap) {
+            let cell = &mut game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1];
+            if *cell == 0 {
+                *cell = 1;
+            }
+        }
+
+        // See whether we should show the current level item
+        // It should be a 2 percent chance of showing the item. Classic games have no items.
+        let show_item = if classic { 100 } else { rng.gen_range(1..100) };
+        if show_item <= 5 && !item.visible {
+            item.visible = true;
+            events.push(GameEvent::ItemRevealed);
+        }
+
+        // Also make sure that the player is not standing on a newly created junk pile..
+        if game_board_data[player.pos_y as usize - 1][player.pos_x as usize - 1] != 0 {
+            player.is_alive = false;
+            let killer = dumb_robots
+                .iter()
+                .find(|robot| robot.pos_x == player.pos_x && robot.pos_y == player.pos_y)
+                .filter(|_| {
+                    game_board_data[player.pos_y as usize - 1][player.pos_x as usize - 1] == 1
+                })
+                .map_or(Killer::JunkHeap, |robot| Killer::Robot(robot.kind));
+            caught(death, killer, player.pos_x, player.pos_y);
+        }
+
+        // Check if the player is standing on the item
+        if player.pos_x == item.pos_x
+            && player.pos_y == item.pos_y
+            && item.visible
+            && !item.picked_up
+        {
+            item.picked_up = true;
+            if item.kind == 1 {
+                player.invincible = true;
+            } else if item.kind == 2 {
+                player.bombs += 1;
+            }
+            events.push(GameEvent::ItemPickedUp { kind: item.kind });
+        }
+    }
+
+    fn teleport_player(&mut self, try_safe: bool) -> GameEvent {
+        self.state.teleports_used += 1;
+        self.level_stats.teleports += 1;
+        if self.classic {
+            // Like BSD robots: any free cell will do, even one right next to a robot
+            (self.player.pos_x, self.player.pos_y) = self.free_position();
+            return GameEvent::Teleported { safe: false };
+        }
+        let player = &mut self.player;
+        let size = self.size;
+
+        // The spots that are at least two steps away from every robot. On a crowded board there
+        // may be none, and then even a safe teleport can't do better than an unsafe one.
+        let mut near_robot = vec![vec![false; size.width as usize + 2]; size.height as usize + 2];
+        for robot in &self.robots {
+            for row in &mut near_robot[robot.pos_y as usize - 1..=robot.pos_y as usize + 1] {
+                row[robot.pos_x as usize - 1..=robot.pos_x as usize + 1].fill(true);
+            }
+        }
+        let is_safe = |x: i32, y: i32| !near_robot[y as usize][x as usize];
+        let any_safe = (1..size.height).any(|y| (1..size.width).any(|x| is_safe(x, y)));
+
+        let mut safe_teleport = false;
+        // Check if the player has any safe teleports left
+        if player.safe_teleports > 0 && try_safe && any_safe {
+            safe_teleport = true;
+            player.safe_teleports -= 1;
+        }
+
+        let rng = &mut self.rng;
+        let mut new_x = rng.gen_range(1..size.width);
+        let mut new_y = rng.gen_range(1..size.height);
+
+        // Is this a safe teleport?
+        if safe_teleport {
+            while !is_safe(new_x, new_y) {
+                new_x = rng.gen_range(1..size.width);
+                new_y = rng.gen_range(1..size.height);
+            }
+        }
+        player.pos_x = new_x;
+        player.pos_y = new_y;
+
+        GameEvent::Teleported {
+            safe: safe_teleport,
+        }
+    }
+
+    // Generate level
+    fn generate_level(&mut self) {
+        // Clear the game board..
+        self.board
+            .iter_mut()
+            .for_each(|row| row.iter_mut().for_each(|cell| *cell = 0));
+
+        // Add one additional safe teleport per level
+        if !self.classic {
+            self.player.safe_teleports += 1;
+        }
+        self.player.invincible = false;
+
+        // Clear the old junk piles vector and the dumb_robots one
+        self.level_stats = LevelStats::default();
+        self.robots.clear();
+        self.junk_heaps.clear();
+
+        // Update the level drop item
+        let item = &mut self.item;
+        item.pos_x = self.rng.gen_range(1..self.size.width);
+        item.pos_y = self.rng.gen_range(1..self.size.height);
+        item.level = self.state.level;
+        item.kind = self.rng.gen_range(1..3);
+        item.visible = false;
+        item.picked_up = false;
+
+        // Add dumb robots, super robots and killer robots.
+        let level = self.state.level;
+        // The numbers are made for the standard board, so scale them with the area of the board,
+        // and never fill more than half of the spots a robot can be placed on
+        let default_area = BOARD_WIDTH * BOARD_HEIGHT;
+        let area = self.size.width * self.size.height;
+        let mut room = (self.size.width - 1) * (self.size.height - 1) / 2;
+        for kind in RobotKind::ALL {
+            let count = match self.classic {
+                true if kind.classic() => level * 10,
+                true => 0,
+                false => kind.count(level, self.teleporting_robot_level),
+            };
+            let count = ((count * area + default_area / 2) / default_area).min(room);
+            room -= count;
+            for _ in 0..count {
+                let (p_x, p_y) = self.free_position();
+
+                self.robots.push(DumbRobot {
+                    pos_x: p_x,
+                    pos_y: p_y,
+                    is_scrap: false,
+                    kind,
+                    turns: 0,
+                    damaged: false,
+                });
+                // Add the robot to the game board array
+                self.board[p_y as usize - 1][p_x as usize - 1] = 1;
+            }
+        }
+
+        // Setup the player
+        let (p_x, p_y) = self.free_position();
+        self.player.pos_x = p_x;
+        self.player.pos_y = p_y;
+    }
+
+    /// Picks a random position on the board that is not occupied.
+    fn free_position(&mut self) -> (i32, i32) {
+        let (columns, rows) = (1..self.size.width, 1..self.size.height);
+        loop {
+            let position =
+                try_free_position(&mut self.rng, &self.board, columns.clone(), rows.clone());
+            if let Some(position) = position {
+                return position;
+            }
+        }
+    }
+}
+
+// Remembers what caught the player, unless something else already did
+fn caught(death: &mut Option<Death>, killer: Killer, pos_x: i32, pos_y: i32) {
+    death.get_or_insert(Death {
+        killer,
+        pos_x,
+        pos_y,
+        teleported: false,
+    });
+}
+
+/// How a robot was scrapped.
+enum Scrap {
+    Collision,
+    Bomb,
+    Junk,
+}
+
+// Picks a random position within the columns and rows, if nothing is there
+fn try_free_position(
+    rng: &mut GameRng, board: &[Vec<i32>], columns: Range<i32>, rows: Range<i32>,
+) -> Option<(i32, i32)> {
+    let p_x = rng.gen_range(columns);
+    let p_y = rng.gen_range(rows);
+    (board[p_y as usize - 1][p_x as usize - 1] == 0).then_some((p_x, p_y))
+}
+
+// A free position near the player for a teleporting robot to land on, but not right next to the
+// player. It gives up after a few tries, when there is little room around the player.
+fn teleport_destination(
+    rng: &mut GameRng, board: &[Vec<i32>], size: BoardSize, player: (i32, i32),
+) -> Option<(i32, i32)> {
+    let columns =
+        (player.0 - TELEPORT_RANGE).max(1)..(player.0 + TELEPORT_RANGE + 1).min(size.width);
+    let rows = (player.1 - TELEPORT_RANGE).max(1)..(player.1 + TELEPORT_RANGE + 1).min(size.height);
+    (0..10).find_map(|_| {
+        try_free_position(rng, board, columns.clone(), rows.clone())
+            .filter(|(x, y)| (x - player.0).abs() >= 2 || (y - player.1).abs() >= 2)
+    })
+}
+
+// The number of robots in every cell that has any
+fn count_robots(robots: &[DumbRobot]) -> HashMap<(i32, i32), usize> {
+    let mut robots_per_cell: HashMap<(i32, i32), usize> = HashMap::new();
+    for robot in robots.iter().filter(|robot| !robot.is_scrap) {
+        *robots_per_cell
+            .entry((robot.pos_x, robot.pos_y))
+            .or_default() += 1;
+    }
+    robots_per_cell
+}
+
+fn clear_board(board: &mut [Vec<i32>]) {
+    board
+        .iter_mut()
+        .for_each(|row| row.iter_mut().for_each(|cell| *cell = 0));
+}
+
+// An invincible player wrecks the robot that tries to catch them, which leaves its junk behind
+fn break_down(
+    robot: &mut DumbRobot, player: &mut Player, junk_heaps: &mut Vec<JunkHeap>,
+    stats: &mut LevelStats,
+) {
+    scrap(robot, stats, Scrap::Collision);
+    junk_heaps.push(JunkHeap {
+        pos_x: robot.pos_x,
+        pos_y: robot.pos_y,
+    });
+    player.score += robot.kind.score();
+    player.invincible = false;
+}
+
+// Scraps the robot, and counts it for the level summary unless it already was scrap
+fn scrap(robot: &mut DumbRobot, stats: &mut LevelStats, cause: Scrap) {
+    if !robot.is_scrap {
+        stats.scrapped[robot.kind as usize] += 1;
+        match cause {
+            Scrap::Collision => stats.collisions += 1,
+            Scrap::Bomb => stats.bombed += 1,
+            Scrap::Junk => stats.junked += 1,
+        }
+    }
+    robot.is_scrap = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot_kind::TELEPORTING_ROBOT_LEVEL;
+
+    const SIZE: BoardSize = BoardSize {
+        width: 20,
+        height: 10,
+    };
+
+    // A robot in the far corner, which keeps the level going while the others are scrapped
+    const BYSTANDER: (RobotKind, (i32, i32)) = (RobotKind::Robot, (20, 10));
+
+    // A game with nothing but the player, these robots and these junk heaps on the board
+    fn game(player: (i32, i32), robots: &[(RobotKind, (i32, i32))], junk: &[(i32, i32)]) -> Engine {
+        let mut engine = Engine::new("test", 1, SIZE, false, TELEPORTING_ROBOT_LEVEL);
+        (engine.player.pos_x, engine.player.pos_y) = player;
+        engine.robots = robots
+            .iter()
+            .map(|(kind, (pos_x, pos_y))| DumbRobot {
+                pos_x: *pos_x,
+                pos_y: *pos_y,
+                is_scrap: false,
+                kind: *kind,
+                turns: 0,
+                damaged: false,
+            })
+            .collect();
+        engine.junk_heaps = junk
+            .iter()
+            .map(|(pos_x, pos_y)| JunkHeap {
+                pos_x: *pos_x,
+                pos_y: *pos_y,
+            })
+            .collect();
+        // Keep the level item out of the way
+        engine.item.visible = true;
+        engine.item.picked_up = true;
+        clear_board(&mut engine.board);
+        for junk in &engine.junk_heaps {
+            engine.board[junk.pos_y as usize - 1][junk.pos_x as usize - 1] = 2;
+        }
+        for robot in &engine.robots {
+            engine.board[robot.pos_y as usize - 1][robot.pos_x as usize - 1] = 1;
+        }
+        engine
+    }
+
+    // A robot as its position, kind, whether it is scrap or damaged, and its turns
+    type Robot = ((i32, i32), i32, bool, bool, i32);
+
+    // The robots in a fixed order
+    fn robots(engine: &Engine) -> Vec<Robot> {
+        let mut robots: Vec<_> = engine
+            .robots
+            .iter()
+            .map(|robot| {
+                let position = (robot.pos_x, robot.pos_y);
+                let kind = robot.kind.id();
+                (position, kind, robot.is_scrap, robot.damaged, robot.turns)
+            })
+            .collect();
+        robots.sort();
+        robots
+    }
+
+    fn junk(engine: &Engine) -> Vec<(i32, i32)> {
+        let mut junk: Vec<_> = engine
+            .junk_heaps
+            .iter()
+            .map(|junk| (junk.pos_x, junk.pos_y))
+            .collect();
+        junk.sort();
+        junk
+    }
+
+    fn bonus(events: &[GameEvent]) -> Option<i32> {
+        events.iter().find_map(|event| match event {
+            GameEvent::LevelCleared { bonus, .. } => Some(*bonus),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn bombs_run_out() {
+        let mut engine = game((10, 5), &[(RobotKind::Robot, (11, 6)), BYSTANDER], &[]);
+        assert_eq!(engine.apply(Action::Bomb), vec![GameEvent::Blocked]);
+
+        engine.player.bombs = 1;
+        let events = engine.apply(Action::Bomb);
+        assert!(matches!(events[0], GameEvent::BombExploded { .. }));
+        assert_eq!(engine.player.bombs, 0);
+        assert_eq!(engine.state.bombs_used, 1);
+        assert_eq!(junk(&engine), vec![(11, 6)]);
+        assert_eq!(engine.apply(Action::Bomb), vec![GameEvent::Blocked]);
+    }
+
+    #[test]
+    fn every_turn_counts_while_waiting_for_the_end() {
+        let mut engine = game((10, 5), &[BYSTANDER], &[]);
+        engine.apply(Action::WaitForEnd);
+        engine.tick();
+        engine.tick();
+        assert_eq!(engine.state.turn, 3);
+        assert_eq!(engine.level_stats.turns, 3);
+    }
+
+    #[test]
+    fn a_super_robot_catches_the_player_from_where_it_stands() {
+        let mut engine = game((10, 5), &[(RobotKind::SuperRobot, (11, 6))], &[]);
+        assert!(engine.apply(Action::Wait).contains(&GameEvent::PlayerDied));
+        let death = Death {
+            killer: Killer::Robot(RobotKind::SuperRobot),
+            pos_x: 11,
+            pos_y: 6,
+            teleported: false,
+        };
+        assert_eq!(engine.death, Some(death));
+        assert_eq!(robots(&engine), vec![((11, 6), 2, false, false, 0)]);
+
+        // It stays there while the others make their final move, and that is no turn
+        engine.tick();
+        assert_eq!(robots(&engine), vec![((11, 6), 2, false, false, 0)]);
+        assert_eq!(engine.state.turn, 1);
+    }
+
+    #[test]
+    fn robots_move_the_same_in_any_order() {
+        let size = BoardSize {
+            width: 30,
+            height: 15,
+        };
+        for seed in 0..20 {
+            let mut engine = Engine::new("test", seed, size, false, TELEPORTING_ROBOT_LEVEL);
+            let mut reversed = Engine::new("test", seed, size, false, TELEPORTING_ROBOT_LEVEL);
+            for engine in [&mut engine, &mut reversed] {
+                engine.state.level = 14;
+                engine.generate_level();
+            }
+            for _ in 0..50 {
+                reversed.robots.reverse();
+                let events = engine.apply(Action::Wait);
+                assert_eq!(events, reversed.apply(Action::Wait), "seed {}", seed);
+                assert_eq!(robots(&engine), robots(&reversed), "seed {}", seed);
+                assert_eq!(junk(&engine), junk(&reversed), "seed {}", seed);
+                assert_eq!(engine.player.score, reversed.player.score);
+                assert_eq!(engine.death, reversed.death);
+                assert_eq!(engine.rng.draws(), reversed.rng.draws());
+                if !engine.player.is_alive || bonus(&events).is_some() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_lowest_kind_id_takes_the_blame_in_any_order() {
+        let catchers = [(RobotKind::SuperRobot, (11, 6)), (RobotKind::Robot, (9, 5))];
+        for reverse in [false, true] {
+            let mut engine = game((10, 5), &catchers, &[]);
+            if reverse {
+                engine.robots.reverse();
+            }
+            assert!(engine.apply(Action::Wait).contains(&GameEvent::PlayerDied));
+            let death = Death {
+                killer: Killer::Robot(RobotKind::Robot),
+                pos_x: 10,
+                pos_y: 5,
+                teleported: false,
+            };
+            assert_eq!(engine.death, Some(death));
+        }
+    }
+
+    #[test]
+    fn an_invincible_player_wrecks_every_robot_that_reaches_them() {
+        let catchers = [
+            (RobotKind::Robot, (9, 5)),
+            (RobotKind::Robot, (11, 5)),
+            (RobotKind::SuperRobot, (11, 6)),
+            BYSTANDER,
+        ];
+        let mut engine = game((10, 5), &catchers, &[]);
+        engine.player.invincible = true;
+        engine.apply(Action::Wait);
+        assert!(engine.player.is_alive);
+        assert!(!engine.player.invincible);
+        assert_eq!(engine.player.score, 3);
+        assert_eq!(junk(&engine), vec![(9, 5), (11, 5), (11, 6)]);
+        assert_eq!(engine.level_stats.collisions, 3);
+    }
+
+    #[test]
+    fn an_armoured_robot_bounces_off_junk() {
+        let robots_before = [(RobotKind::ArmouredRobot, (5, 5)), BYSTANDER];
+        let mut engine = game((10, 5), &robots_before, &[(6, 5)]);
+        engine.apply(Action::Wait);
+        assert_eq!(robots(&engine)[0], ((5, 5), 1, false, true, 0));
+        assert_eq!(junk(&engine), vec![(6, 5)]);
+        assert_eq!(engine.player.score, 0);
+
+        // Without its armour it is scrapped like any other robot
+        engine.apply(Action::Wait);
+        assert_eq!(robots(&engine)[0], ((6, 5), 1, true, true, 0));
+    }
+
+    #[test]
+    fn an_armoured_robot_bounces_off_another_robot() {
+        let robots_before = [
+            (RobotKind::ArmouredRobot, (8, 4)),
+            (RobotKind::Robot, (8, 6)),
+        ];
+        let mut engine = game((10, 5), &robots_before, &[]);
+        engine.apply(Action::Wait);
+        let robots_after = vec![((8, 4), 1, false, true, 0), ((9, 5), 1, false, false, 0)];
+        assert_eq!(robots(&engine), robots_after);
+        assert!(engine.junk_heaps.is_empty());
+    }
+
+    #[test]
+    fn a_junk_pusher_pushes_the_heap_it_runs_into() {
+        let mut engine = game((10, 5), &[(RobotKind::JunkPusher, (5, 5))], &[(6, 5)]);
+        engine.apply(Action::Wait);
+        assert_eq!(robots(&engine), vec![((6, 5), 6, false, false, 0)]);
+        assert_eq!(junk(&engine), vec![(7, 5)]);
+        assert_eq!(engine.board[4][5], 1);
+        assert_eq!(engine.board[4][6], 2);
+    }
+
+    #[test]
+    fn a_heap_pushed_onto_the_player_buries_them() {
+        let mut engine = game((10, 5), &[(RobotKind::JunkPusher, (8, 5))], &[(9, 5)]);
+        assert!(engine.apply(Action::Wait).contains(&GameEvent::PlayerDied));
+        let death = Death {
+            killer: Killer::JunkHeap,
+            pos_x: 10,
+            pos_y: 5,
+            teleported: false,
+        };
+        assert_eq!(engine.death, Some(death));
+        assert_eq!(junk(&engine), vec![(10, 5)]);
+    }
+
+    #[test]
+    fn a_heap_is_not_pushed_off_the_board() {
+        let mut engine = game((20, 5), &[(RobotKind::JunkPusher, (19, 2))], &[(20, 3)]);
+        engine.apply(Action::Wait);
+        assert_eq!(robots(&engine), vec![((19, 2), 6, false, false, 0)]);
+        assert_eq!(junk(&engine), vec![(20, 3)]);
+    }
+
+    #[test]
+    fn a_heap_pushed_from_two_sides_stays_put() {
+        let pushers = [
+            (RobotKind::JunkPusher, (5, 6)),
+            (RobotKind::JunkPusher, (7, 6)),
+            BYSTANDER,
+        ];
+        let mut engine = game((6, 2), &pushers, &[(6, 5)]);
+        engine.apply(Action::Wait);
+        assert_eq!(junk(&engine), vec![(6, 5)]);
+        assert_eq!(engine.level_stats.junked, 2);
+        assert_eq!(engine.player.score, 2);
+    }
+
+    #[test]
+    fn classic_robots_are_worth_the_same_however_they_are_scrapped() {
+        let robots_before = [
+            (RobotKind::Robot, (8, 4)),
+            (RobotKind::Robot, (8, 6)),
+            (RobotKind::Robot, (5, 5)),
+            BYSTANDER,
+        ];
+        let mut engine = game((10, 5), &robots_before, &[(6, 5)]);
+        engine.classic = true;
+        engine.apply(Action::Wait);
+        assert_eq!(engine.level_stats.collisions, 2);
+        assert_eq!(engine.level_stats.junked, 1);
+        assert_eq!(engine.player.score, 3 * ROBOT_SCORE);
+    }
+
+    #[test]
+    fn waiting_for_the_end_earns_a_bonus() {
+        let robots_before = [(RobotKind::Robot, (8, 4)), (RobotKind::Robot, (8, 6))];
+
+        let mut engine = game((10, 5), &robots_before, &[]);
+        assert_eq!(bonus(&engine.apply(Action::Wait)), Some(0));
+        assert_eq!(engine.player.score, 4);
+
+        let mut engine = game((10, 5), &robots_before, &[]);
+        assert_eq!(
+            bonus(&engine.apply(Action::WaitForEnd)),
+            Some(2 * WAIT_BONUS)
+        );
+        assert_eq!(engine.player.score, 4 + 2 * WAIT_BONUS);
+        assert!(!engine.state.wait_for_end);
+
+        let mut engine = game((10, 5), &robots_before, &[]);
+        engine.classic = true;
+        assert_eq!(
+            bonus(&engine.apply(Action::WaitForEnd)),
+            Some(2 * ROBOT_SCORE)
+        );
+        assert_eq!(engine.player.score, 4 * ROBOT_SCORE);
+    }
+
+    #[test]
+    fn undo_takes_back_the_random_numbers_too() {
+        let mut engine = game((10, 5), &[BYSTANDER], &[]);
+        engine.practice = true;
+        let draws = engine.rng.draws();
+
+        engine.apply(Action::Teleport);
+        let teleported = (engine.player.pos_x, engine.player.pos_y);
+        assert!(engine.can_undo());
+        assert_eq!(engine.apply(Action::Undo), vec![GameEvent::Undone]);
+        assert_eq!((engine.player.pos_x, engine.player.pos_y), (10, 5));
+        assert_eq!(engine.rng.draws(), draws);
+        assert_eq!(engine.state.teleports_used, 0);
+
+        // So the same action plays out the same way again
+        engine.apply(Action::Teleport);
+        assert_eq!((engine.player.pos_x, engine.player.pos_y), teleported);
+    }
+
+    #[test]
+    fn blocked_moves_are_no_turns() {
+        let mut engine = game((10, 5), &[BYSTANDER], &[(11, 5)]);
+        engine.practice = true;
+        assert_eq!(engine.apply(Action::Move(1, 0)), vec![GameEvent::Blocked]);
+        assert!(!engine.can_undo());
+        assert_eq!(engine.state.turn, 0);
+        assert_eq!(engine.apply(Action::Undo), vec![GameEvent::Blocked]);
+    }
+}
@@ -0,0 +1,176 @@
+/// Module for drawing the screen without flicker.
+///
+/// Instead of clearing the terminal and printing everything again every turn, the display
+/// functions draw into a `Frame`, a grid of characters that starts out blank. The `Screen`
+/// remembers the frame that is on the terminal, and presenting the next frame only writes the
+/// cells that changed, queued up and sent to the terminal in a single write.
+///
+/// Every cell has a `Style` with the colours it is drawn in.
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+};
+use std::io::{self, prelude::*};
+
+/// The colours of a cell. `None` leaves the colour of the terminal as it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+}
+
+type Cell = (char, Style);
+
+const BLANK: Cell = (
+    ' ',
+    Style {
+        foreground: None,
+        background: None,
+    },
+);
+
+/// A grid of characters, which grows to fit whatever is drawn on it.
+#[derive(Clone, Default)]
+pub struct Frame {
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Frame {
+    /// Puts text on the frame at the given column and row. Tabs move on to the next multiple of
+    /// eight columns, like they do on the terminal.
+    pub fn print(&mut self, x: i32, y: i32, text: &str, style: Style) {
+        if x < 0 || y < 0 {
+            return;
+        }
+        let (mut x, y) = (x as usize, y as usize);
+        if self.rows.len() <= y {
+            self.rows.resize(y + 1, Vec::new());
+        }
+        let row = &mut self.rows[y];
+        for c in text.chars() {
+            if c == '\t' {
+                x = (x / 8 + 1) * 8;
+                continue;
+            }
+            if row.len() <= x {
+                row.resize(x + 1, BLANK);
+            }
+            row[x] = (c, style);
+            x += 1;
+        }
+    }
+
+    fn cell(&self, x: usize, y: usize) -> Cell {
+        self.rows
+            .get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+            .unwrap_or(BLANK)
+    }
+
+    fn row_width(&self, y: usize) -> usize {
+        self.rows.get(y).map_or(0, |row| row.len())
+    }
+}
+
+pub struct Screen {
+    frame: Frame,
+    /// The frame that is on the terminal, or `None` if the terminal has to be redrawn in full
+    shown: Option<Frame>,
+    terminal_size: Option<(u16, u16)>,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen {
+            frame: Frame::default(),
+            shown: None,
+            terminal_size: None,
+        }
+    }
+
+    /// Starts a new, blank frame.
+    pub fn clear(&mut self) {
+        self.frame = Frame::default();
+    }
+
+    pub fn print(&mut self, x: i32, y: i32, text: &str) {
+        self.frame.print(x, y, text, Style::default());
+    }
+
+    pub fn print_styled(&mut self, x: i32, y: i32, text: &str, style: Style) {
+        self.frame.print(x, y, text, style);
+    }
+
+    /// Writes the cells that changed since the last frame to the terminal, and leaves the
+    /// cursor on the line below the frame.
+    pub fn present(&mut self) {
+        let mut out = Vec::new();
+
+        // After a resize the terminal may have wrapped or scrolled what was on it, so start over
+        let terminal_size = terminal::size().ok();
+        if terminal_size != self.terminal_size {
+            self.shown = None;
+        }
+        let shown = match self.shown.take() {
+            Some(shown) => shown,
+            None => {
+                queue!(out, Clear(ClearType::All)).unwrap();
+                Frame::default()
+            }
+        };
+
+        let frame = &self.frame;
+        let mut current = Style::default();
+        for y in 0..frame.rows.len().max(shown.rows.len()) {
+            let width = frame.row_width(y).max(shown.row_width(y));
+            let mut x = 0;
+            while x < width {
+                if frame.cell(x, y) == shown.cell(x, y) {
+                    x += 1;
+                    continue;
+                }
+                // Write the whole run of changed cells at once, switching colours where needed
+                queue!(out, MoveTo(x as u16, y as u16)).unwrap();
+                while x < width && frame.cell(x, y) != shown.cell(x, y) {
+                    let (c, style) = frame.cell(x, y);
+                    if style != current {
+                        set_style(&mut out, style);
+                        current = style;
+                    }
+                    queue!(out, Print(c)).unwrap();
+                    x += 1;
+                }
+            }
+        }
+        if current != Style::default() {
+            queue!(out, ResetColor).unwrap();
+        }
+        // Keys typed outside of raw mode are echoed at the cursor, so wipe everything below
+        queue!(
+            out,
+            MoveTo(0, frame.rows.len() as u16),
+            Clear(ClearType::FromCursorDown)
+        )
+        .unwrap();
+
+        let mut stdout = io::stdout();
+        stdout.write_all(&out).unwrap();
+        stdout.flush().unwrap();
+
+        self.shown = Some(self.frame.clone());
+        self.terminal_size = terminal_size;
+    }
+}
+
+fn set_style(out: &mut Vec<u8>, style: Style) {
+    queue!(out, ResetColor).unwrap();
+    if let Some(color) = style.foreground {
+        queue!(out, SetForegroundColor(color)).unwrap();
+    }
+    if let Some(color) = style.background {
+        queue!(out, SetBackgroundColor(color)).unwrap();
+    }
+}
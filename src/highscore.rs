@@ -1,9 +1,13 @@
 /// Module for handling highscores in the game.
 ///
-/// This module provides functions for adding highscores, validating highscore files,
-/// showing highscores, and handling highscore commands.
+/// This module provides functions for adding highscores, validating and migrating highscore
+/// files, showing highscores, and handling highscore commands.
 ///
-use crate::structs::{Args, GameState, Player};
+/// The highscore file may be shared by several players at once. All access goes through an
+/// advisory lock, and the file is only ever appended to or replaced as a whole.
+///
+use crate::engine::Engine;
+use crate::structs::{Args, GameMode, GameState, HighscoreQuery, OutputFormat, Player, SortKey};
 use clap::CommandFactory;
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
@@ -11,46 +15,346 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
 };
-use std::fs::OpenOptions;
+use std::fs::{self, File, OpenOptions, TryLockError};
 use std::io::{self, prelude::*};
+use std::str::FromStr;
+
+/// The first line of a highscore file is `#robots-highscore;<version>`. Files without it are in
+/// the original `name;score;level;timestamp` format (version 0). Older files are migrated to the
+/// current version on startup.
+const HIGHSCORE_VERSION: u32 = 2;
+const HIGHSCORE_HEADER: &str = "#robots-highscore;";
+
+/// How often, and how long apart, to try locking a highscore file that another game is using.
+const LOCK_ATTEMPTS: u32 = 40;
+const LOCK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// One finished game in the highscore file.
+///
+/// Version 2 lines hold `name;score;level;timestamp;seed;turns;robots;bombs;teleports;mode`.
+/// Version 1 lacked the teleports, and version 0 only had the first four fields. Games that were
+/// migrated from an older version leave the fields they don't know empty.
+pub struct HighscoreRecord {
+    pub username: String,
+    pub score: i32,
+    pub level: i32,
+    pub timestamp: u64,
+    pub seed: Option<u64>,
+    pub turns: Option<i32>,
+    pub robots_destroyed: Option<i32>,
+    pub bombs_used: Option<i32>,
+    pub teleports_used: Option<i32>,
+    pub mode: String,
+}
+
+impl HighscoreRecord {
+    fn to_line(&self) -> String {
+        format!(
+            "{};{};{};{};{};{};{};{};{};{}",
+            self.username,
+            self.score,
+            self.level,
+            self.timestamp,
+            optional(self.seed),
+            optional(self.turns),
+            optional(self.robots_destroyed),
+            optional(self.bombs_used),
+            optional(self.teleports_used),
+            self.mode
+        )
+    }
+
+    // The fields are split off from the right, so a username containing a ; survives
+    fn parse(line: &str, version: u32) -> Result<HighscoreRecord, String> {
+        let fields = match version {
+            0 => 4,
+            1 => 9,
+            _ => 10,
+        };
+        let mut parts: Vec<&str> = line.rsplitn(fields, ';').collect();
+        if parts.len() != fields {
+            return Err(format!("expected {} fields, found {}", fields, parts.len()));
+        }
+        parts.reverse();
+        // Fields that this version of the file doesn't have yet
+        let field = |index: usize| parts.get(index).copied().unwrap_or("");
+
+        Ok(HighscoreRecord {
+            username: parts[0].to_string(),
+            score: number(parts[1], "score")?,
+            level: number(parts[2], "level")?,
+            timestamp: number(parts[3], "timestamp")?,
+            seed: optional_number(field(4), "seed")?,
+            turns: optional_number(field(5), "turns")?,
+            robots_destroyed: optional_number(field(6), "robots")?,
+            bombs_used: optional_number(field(7), "bombs")?,
+            teleports_used: if version >= 2 {
+                optional_number(field(8), "teleports")?
+            } else {
+                None
+            },
+            mode: match version {
+                0 => "normal".to_string(),
+                _ => parts[fields - 1].to_string(),
+            },
+        })
+    }
+}
+
+// The version of a highscore file, from its first line
+fn file_version(content: &str) -> Result<u32, String> {
+    match content.lines().next() {
+        Some(line) if line.starts_with(HIGHSCORE_HEADER) => number(
+            line.trim_start_matches(HIGHSCORE_HEADER),
+            "highscore file version",
+        ),
+        _ => Ok(0),
+    }
+}
 
-pub fn add_highscore(args: &Args, player: &Player, state: &GameState) {
-    let mut file = OpenOptions::new().append(true).open(&args.path).unwrap();
+fn header() -> String {
+    format!("{}{}", HIGHSCORE_HEADER, HIGHSCORE_VERSION)
+}
 
+fn number<T: FromStr>(value: &str, field: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("{} '{}' is not a number", field, value))
+}
+
+fn optional_number<T: FromStr>(value: &str, field: &str) -> Result<Option<T>, String> {
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        number(value, field).map(Some)
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+pub fn add_highscore(args: &Args, engine: &Engine) {
     let timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
+        .unwrap_or_default()
         .as_secs();
-    if let Err(e) = writeln!(
-        file,
-        "{};{};{};{}",
-        player.username, player.score, state.level, timestamp
-    ) {
-        eprintln!("Couldn't write to file: {}", e);
+    let record = HighscoreRecord {
+        username: engine.player.username.clone(),
+        score: engine.player.score,
+        level: engine.state.level,
+        timestamp,
+        seed: Some(engine.seed),
+        turns: Some(engine.state.turn),
+        robots_destroyed: Some(engine.state.robots_destroyed),
+        bombs_used: Some(engine.state.bombs_used),
+        teleports_used: Some(engine.state.teleports_used),
+        mode: engine.mode().name().to_string(),
+    };
+    if let Err(err) = append_highscore(&args.path, &record) {
+        eprintln!("Couldn't save the highscore: {}", err);
+    }
+}
+
+fn append_highscore(path: &str, record: &HighscoreRecord) -> Result<(), String> {
+    let _lock = lock_highscores(path, true)?;
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|err| format!("Couldn't open highscore file {}: {}", path, err))?;
+
+    // Someone may have removed the file since it was validated
+    let empty = file.metadata().map(|meta| meta.len() == 0).unwrap_or(false);
+    let mut content = String::new();
+    if empty {
+        content.push_str(&header());
+        content.push('\n');
     }
+    content.push_str(&record.to_line());
+    content.push('\n');
+
+    // A single write, so a reader without the lock never sees half a line
+    file.write_all(content.as_bytes())
+        .map_err(|err| format!("Couldn't write to highscore file {}: {}", path, err))
 }
 
 fn validate_highscore_file(path: &str) {
     println!("Validating highscore file at path: {}", path);
-    match std::fs::read_to_string(path) {
-        Ok(_) => {
+    let _lock = match lock_highscores(path, true) {
+        Ok(lock) => lock,
+        Err(err) => {
+            eprintln!("{}", err);
             return;
         }
-        Err(_) => match std::fs::write(path, "") {
-            Ok(_) => {
-                println!("Highscorefile created successfully");
+    };
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            if content.trim().is_empty() {
+                if let Err(err) = write_atomically(path, &format!("{}\n", header())) {
+                    eprintln!("Error writing highscore file {}: {}", path, err);
+                }
+            } else {
+                match file_version(&content) {
+                    Ok(version) if version < HIGHSCORE_VERSION => {
+                        migrate_highscore_file(path, &content, version)
+                    }
+                    Ok(version) if version > HIGHSCORE_VERSION => eprintln!(
+                        "Highscore file {} was written by a newer version of the game",
+                        path
+                    ),
+                    Ok(_) => (),
+                    Err(err) => eprintln!("Malformed highscore file {}: {}", path, err),
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            match write_atomically(path, &format!("{}\n", header())) {
+                Ok(_) => {
+                    println!("Highscorefile created successfully");
+                }
+                Err(err) => {
+                    eprintln!("Error creating highscore file {}: {}", path, err);
+                }
             }
+        }
+        Err(err) => {
+            eprintln!("Error reading highscore file {}: {}", path, err);
+        }
+    }
+
+    // Tell the player up front if their score can't be saved
+    if let Err(err) = OpenOptions::new().append(true).open(path) {
+        eprintln!(
+            "Highscore file {} can't be written, your score will not be saved: {}",
+            path, err
+        );
+    }
+}
+
+// Rewrites an older highscore file in the current format. Lines that can't be read are
+// reported and kept as comments, so nothing is lost.
+fn migrate_highscore_file(path: &str, content: &str, version: u32) {
+    println!("Migrating highscore file {} to the current format", path);
+    let mut lines = vec![header()];
+    for (line, number) in content.lines().zip(1..) {
+        if line.trim().is_empty() || line.starts_with(HIGHSCORE_HEADER) {
+            continue;
+        }
+        if line.starts_with('#') {
+            lines.push(line.to_string());
+            continue;
+        }
+        match HighscoreRecord::parse(line, version) {
+            Ok(record) => lines.push(record.to_line()),
             Err(err) => {
-                eprintln!("Error creating highscore file {}: {}", path, err);
+                eprintln!("Malformed highscore on line {}: {}", number, err);
+                lines.push(format!("# {}", line));
+            }
+        }
+    }
+    if let Err(err) = write_atomically(path, &(lines.join("\n") + "\n")) {
+        eprintln!("Error migrating highscore file {}: {}", path, err);
+    }
+}
+
+/// Holds the advisory lock on a highscore file until it is dropped.
+///
+/// The lock is taken on a separate `<path>.lock` file rather than on the highscore file itself,
+/// since rewriting the highscore file replaces it with a new one.
+struct HighscoreLock {
+    _file: File,
+}
+
+fn lock_highscores(path: &str, exclusive: bool) -> Result<HighscoreLock, String> {
+    let lock_path = format!("{}.lock", path);
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(|err| format!("Couldn't open lock file {}: {}", lock_path, err))?;
+
+    for _ in 0..LOCK_ATTEMPTS {
+        let result = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+        match result {
+            Ok(()) => return Ok(HighscoreLock { _file: file }),
+            Err(TryLockError::WouldBlock) => std::thread::sleep(LOCK_RETRY_DELAY),
+            Err(TryLockError::Error(err)) => {
+                return Err(format!("Couldn't lock {}: {}", lock_path, err));
             }
-        },
+        }
     }
+    Err(format!(
+        "Highscore file {} is locked by another game, try again later",
+        path
+    ))
 }
 
-pub fn show_highscore(path: &str, player: &Player, gamestate: &GameState) {
+// Writes the content next to the file and renames it into place, so the file is either the
+// old or the new version, never something in between
+fn write_atomically(path: &str, content: &str) -> io::Result<()> {
+    let temp_path = format!("{}.{}.tmp", path, std::process::id());
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        // Keep the permissions of a shared file
+        if let Ok(meta) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, meta.permissions())?;
+        }
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Reads all records from the highscore file, reporting the lines that can't be read.
+pub fn read_highscores(path: &str) -> Vec<HighscoreRecord> {
+    // Reading without the lock is still better than showing no highscores at all
+    let _lock = lock_highscores(path, false)
+        .inspect_err(|err| eprintln!("{}", err))
+        .ok();
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Couldn't read highscore file {}: {}", path, err);
+            return Vec::new();
+        }
+    };
+    // Files that haven't been migrated yet can still be read
+    let version = match file_version(&content) {
+        Ok(version) => version,
+        Err(err) => {
+            eprintln!("Malformed highscore file {}: {}", path, err);
+            return Vec::new();
+        }
+    };
+    let mut records = Vec::new();
+    for (line, number) in content.lines().zip(1..) {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        match HighscoreRecord::parse(line, version) {
+            Ok(record) => records.push(record),
+            Err(err) => eprintln!("Malformed highscore on line {}: {}", number, err),
+        }
+    }
+    records
+}
+
+/// Shows the top 10 of the given mode, and the result of the game that just ended.
+pub fn show_highscore(path: &str, mode: GameMode, player: &Player, gamestate: &GameState) {
     let end_score = player.score;
 
-    let content = top_highscores(&path).join("\n");
+    let content = top_highscores(path, mode).join("\n");
     execute!(io::stdout(), Clear(ClearType::All)).expect("Failed to clear screen");
     execute!(io::stdout(), MoveTo(0, 0)).expect("Failed to move cursor");
     execute!(io::stdout(), Hide).expect("Failed to hide cursor");
@@ -71,15 +375,16 @@ pub fn show_highscore(path: &str, player: &Player, gamestate: &GameState) {
 
 pub fn handle_highscore(args: &Args) {
     let username = &args.username;
-    if username == "show_highscore" && !args.show_highscore {
+    if username == "show_highscore" && !args.show_highscore && args.resume.is_none() {
         Args::command().print_help().unwrap();
         std::process::exit(0);
     }
     let path = &args.path;
-    validate_highscore_file(&path);
+    validate_highscore_file(path);
     if args.show_highscore {
         show_highscore(
-            &path,
+            path,
+            GameMode::Normal,
             &Player {
                 username: "show_highscore".to_string(),
                 score: 0,
@@ -95,50 +400,381 @@ pub fn handle_highscore(args: &Args) {
                 level: 1,
                 wait_for_end: false,
                 bomb_away: false,
+                robots_destroyed: 0,
+                bombs_used: 0,
+                teleports_used: 0,
+                scrapped_while_waiting: 0,
             },
         );
         std::process::exit(0);
     }
 }
 
-fn top_highscores(path: &str) -> Vec<String> {
-    let mut highscores: Vec<(String, i32, i32)> = Vec::new();
-    let content = std::fs::read_to_string(path).unwrap();
-    for line in content.lines() {
-        let parts: Vec<&str> = line.split(";").collect();
-        if parts.len() == 4 {
-            let username = parts[0];
-            let score = parts[1].parse::<i32>().unwrap();
-            let level = parts[2].parse::<i32>().unwrap();
-            highscores.push((username.to_string(), score, level));
+fn top_highscores(path: &str, mode: GameMode) -> Vec<String> {
+    let query = HighscoreQuery {
+        top: 10,
+        user: None,
+        since: None,
+        sort: SortKey::Score,
+        best: false,
+        mode,
+        format: OutputFormat::Table,
+    };
+    format_highscores(" Top 10 highscores:", &query_highscores(path, &query))
+}
+
+/// Reads the highscores and returns the ones matching the query, in the requested order.
+pub fn query_highscores(path: &str, query: &HighscoreQuery) -> Vec<HighscoreRecord> {
+    let mut highscores: Vec<HighscoreRecord> = read_highscores(path)
+        .into_iter()
+        .filter(|record| {
+            query
+                .user
+                .as_ref()
+                .is_none_or(|user| &record.username == user)
+        })
+        .filter(|record| query.since.is_none_or(|since| record.timestamp >= since))
+        .filter(|record| record.mode == query.mode.name())
+        .collect();
+
+    if query.best {
+        // Keep the first of the highest scores of every user
+        let mut best: Vec<HighscoreRecord> = Vec::new();
+        for record in highscores {
+            match best.iter_mut().find(|b| b.username == record.username) {
+                Some(b) if b.score < record.score => *b = record,
+                Some(_) => (),
+                None => best.push(record),
+            }
         }
+        highscores = best;
     }
-    let mut padding = highscores
+
+    match query.sort {
+        SortKey::Score => highscores.sort_by_key(|b| std::cmp::Reverse(b.score)),
+        SortKey::Level => highscores.sort_by_key(|b| std::cmp::Reverse(b.level)),
+        SortKey::Date => highscores.sort_by_key(|b| std::cmp::Reverse(b.timestamp)),
+    }
+    highscores.truncate(query.top);
+    highscores
+}
+
+// Lays the highscores out as a table, with every column as wide as its widest value
+fn format_highscores(title: &str, highscores: &[HighscoreRecord]) -> Vec<String> {
+    let header = ["Player", "Score", "Level", "Date"];
+    let rows: Vec<[String; 4]> = highscores
         .iter()
-        .map(|(username, _, _)| username.len())
-        .max()
-        .unwrap_or(0);
-    if padding < 6 {
-        padding = 6;
-    }
-    highscores.sort_by(|a, b| b.1.cmp(&a.1));
-    let mut result = vec![];
-    result.push(format!(" Top 10 highscores:"));
-    result.push(format!(" {}", "-".repeat(padding + 30)));
-    result.push(format!(
-        " Player{}\tScore\t\tLevel",
-        " ".repeat(padding - 6)
-    ));
-    result.push(format!(" {}", "-".repeat(padding + 30)));
-    for (_, (username, score, level)) in highscores.iter().take(10).enumerate() {
-        result.push(format!(
-            " {}{}\t {}\t\t {}",
-            username,
-            " ".repeat(padding - username.len()),
-            score,
-            level
+        .map(|record| {
+            [
+                record.username.clone(),
+                record.score.to_string(),
+                record.level.to_string(),
+                format_date(record.timestamp),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|column| column.chars().count());
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    // Names and dates are aligned to the left, numbers to the right
+    let format_row = |row: [&str; 4]| {
+        format!(
+            " {:<w0$}  {:>w1$}  {:>w2$}  {:<w3$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3]
+        )
+        .trim_end()
+        .to_string()
+    };
+    let line = format!(" {}", "-".repeat(widths.iter().sum::<usize>() + 6));
+
+    let mut result = vec![
+        title.to_string(),
+        line.clone(),
+        format_row(header),
+        line.clone(),
+    ];
+    for row in &rows {
+        result.push(format_row([&row[0], &row[1], &row[2], &row[3]]));
+    }
+    result.push(line);
+    result
+}
+
+fn highscores_to_json(highscores: &[HighscoreRecord]) -> String {
+    let records: Vec<String> = highscores
+        .iter()
+        .map(|record| {
+            format!(
+                "  {{\"username\": {}, \"score\": {}, \"level\": {}, \"timestamp\": {}, \"date\": \"{}\", \"seed\": {}, \"turns\": {}, \"robots_destroyed\": {}, \"bombs_used\": {}, \"teleports_used\": {}, \"mode\": {}}}",
+                json_string(&record.username),
+                record.score,
+                record.level,
+                record.timestamp,
+                format_date(record.timestamp),
+                json_optional(record.seed),
+                json_optional(record.turns),
+                json_optional(record.robots_destroyed),
+                json_optional(record.bombs_used),
+                json_optional(record.teleports_used),
+                json_string(&record.mode)
+            )
+        })
+        .collect();
+    if records.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[\n{}\n]", records.join(",\n"))
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_optional<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "null".to_string())
+}
+
+fn highscores_to_csv(highscores: &[HighscoreRecord]) -> String {
+    let mut lines = vec![
+        "username,score,level,timestamp,date,seed,turns,robots_destroyed,bombs_used,teleports_used,mode"
+            .to_string(),
+    ];
+    for record in highscores {
+        lines.push(format!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&record.username),
+            record.score,
+            record.level,
+            record.timestamp,
+            format_date(record.timestamp),
+            optional(record.seed),
+            optional(record.turns),
+            optional(record.robots_destroyed),
+            optional(record.bombs_used),
+            optional(record.teleports_used),
+            csv_field(&record.mode)
         ));
     }
-    result.push(format!(" {}", "-".repeat(padding + 30)));
-    return result;
+    lines.join("\n")
+}
+
+// Quotes a field if it contains anything that would break the row apart
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Prints the result of a highscore query, for the highscore subcommand.
+pub fn print_highscores(path: &str, query: &HighscoreQuery) {
+    let highscores = query_highscores(path, query);
+    let content = match query.format {
+        OutputFormat::Table => {
+            let sort = format!("{:?}", query.sort).to_lowercase();
+            let title = format!(" Highscores by {}:", sort);
+            format_highscores(&title, &highscores).join("\n")
+        }
+        OutputFormat::Json => highscores_to_json(&highscores),
+        OutputFormat::Csv => highscores_to_csv(&highscores),
+    };
+    // The output is often piped into other tools, which may stop reading early
+    if let Err(err) = writeln!(io::stdout(), "{}", content) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Couldn't print the highscores: {}", err);
+        }
+    }
+}
+
+/// Parses a YYYY-MM-DD date into seconds since the epoch, at midnight UTC.
+pub fn parse_date(date: &str) -> Result<u64, String> {
+    let invalid = || format!("'{}' is not a date in the form YYYY-MM-DD", date);
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+    let year: i64 = parts[0].parse().map_err(|_| invalid())?;
+    let month: i64 = parts[1].parse().map_err(|_| invalid())?;
+    let day: i64 = parts[2].parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Ok(days as u64 * 86400)
+}
+
+/// Formats seconds since the epoch as a YYYY-MM-DD date, in UTC.
+pub fn format_date(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file of its own for every test, in the temporary directory
+    fn temp_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("robots-{}-{}", std::process::id(), name));
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn record(username: &str) -> HighscoreRecord {
+        HighscoreRecord {
+            username: username.to_string(),
+            score: 120,
+            level: 4,
+            timestamp: 1_700_000_000,
+            seed: Some(42),
+            turns: Some(300),
+            robots_destroyed: None,
+            bombs_used: Some(1),
+            teleports_used: Some(7),
+            mode: "normal".to_string(),
+        }
+    }
+
+    #[test]
+    fn older_highscore_files_are_migrated() {
+        let content =
+            "alice;12;3;1700000000\nbob;;with;semicolons;5;2;1700000001\n\nnot a highscore\n";
+        let path = temp_file("version-0", content);
+        migrate_highscore_file(&path, content, 0);
+        let migrated = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            migrated,
+            "#robots-highscore;2\n\
+             alice;12;3;1700000000;;;;;;normal\n\
+             bob;;with;semicolons;5;2;1700000001;;;;;;normal\n\
+             # not a highscore\n"
+        );
+
+        let content = "#robots-highscore;1\nalice;12;3;1700000000;42;300;25;1;classic\n";
+        let path = temp_file("version-1", content);
+        migrate_highscore_file(&path, content, 1);
+        let migrated = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            migrated,
+            "#robots-highscore;2\nalice;12;3;1700000000;42;300;25;1;;classic\n"
+        );
+    }
+
+    #[test]
+    fn malformed_highscores_are_skipped() {
+        assert!(HighscoreRecord::parse("alice;12;3", 0).is_err());
+        assert!(HighscoreRecord::parse("alice;twelve;3;1700000000", 0).is_err());
+        assert!(HighscoreRecord::parse("alice;12;3;1700000000;x;;;;;normal", 2).is_err());
+
+        let content = "#robots-highscore;2\n\
+                       alice;12;3;1700000000;42;300;25;1;6;normal\n\
+                       alice;12;3\n\
+                       bob;20;4;1700000001;;;;;;practice\n";
+        let path = temp_file("malformed", content);
+        let records = read_highscores(&path);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.lock", path)).unwrap();
+        let usernames: Vec<_> = records.iter().map(|record| &record.username).collect();
+        assert_eq!(usernames, ["alice", "bob"]);
+        assert_eq!(records[0].teleports_used, Some(6));
+        assert_eq!(records[1].seed, None);
+        assert_eq!(records[1].mode, "practice");
+    }
+
+    #[test]
+    fn dates_survive_the_round_trip() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(format_date(1_700_000_000), "2023-11-14");
+        for day in (0..30_000).step_by(7) {
+            let date = format_date(day * 86400 + 3600);
+            assert_eq!(parse_date(&date), Ok(day * 86400), "{}", date);
+        }
+        for date in ["2024-02-29", "2000-02-29", "2099-12-31"] {
+            assert_eq!(format_date(parse_date(date).unwrap()), date);
+        }
+        for date in [
+            "2024-13-01",
+            "2024-01-32",
+            "1969-12-31",
+            "yesterday",
+            "2024-01",
+        ] {
+            assert!(parse_date(date).is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn exported_fields_are_escaped() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(
+            json_string("a \"quote\", a \\ and\ta\nnew line\u{1}"),
+            "\"a \\\"quote\\\", a \\\\ and\\ta\\nnew line\\u0001\""
+        );
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+
+        let records = [record("smith, \"jr\"")];
+        assert_eq!(
+            highscores_to_csv(&records).lines().nth(1),
+            Some("\"smith, \"\"jr\"\"\",120,4,1700000000,2023-11-14,42,300,,1,7,normal")
+        );
+        assert_eq!(
+            highscores_to_json(&records),
+            "[\n  {\"username\": \"smith, \\\"jr\\\"\", \"score\": 120, \"level\": 4, \"timestamp\": 1700000000, \"date\": \"2023-11-14\", \"seed\": 42, \"turns\": 300, \"robots_destroyed\": null, \"bombs_used\": 1, \"teleports_used\": 7, \"mode\": \"normal\"}\n]"
+        );
+        assert_eq!(highscores_to_json(&[]), "[]");
+    }
 }
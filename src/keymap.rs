@@ -0,0 +1,316 @@
+/// Module for the key bindings of the game.
+///
+/// A `Keymap` turns the keys the player presses into `Action`s. It starts from one of the
+/// built-in layouts, chosen with `--layout`: the classic vi keys, the digits of the numpad, or
+/// a WASD layout with the commands moved next to it. The arrow keys, together with Home, End,
+/// Page Up and Page Down for the diagonals, move the player in every layout, and Backspace
+/// undoes a move in practice mode.
+///
+/// A keymap file can bind actions to other keys. It has one `action=key` pair per line, where
+/// several keys are separated by `;`. Keys are single characters or names like `up`, `space`,
+/// `esc` or `f1`. Empty lines and lines starting with `#` are skipped. The keys in the file
+/// replace the keys the layout had for that action, and the first key of every action is the
+/// one shown in the side panel.
+use crate::engine::Action;
+use crate::structs::Layout;
+use crossterm::event::KeyCode;
+use std::fs;
+
+/// The actions that can be bound, with their names in keymap files.
+const ACTIONS: [(&str, Action); 15] = [
+    ("up_left", Action::Move(-1, -1)),
+    ("up", Action::Move(0, -1)),
+    ("up_right", Action::Move(1, -1)),
+    ("left", Action::Move(-1, 0)),
+    ("right", Action::Move(1, 0)),
+    ("down_left", Action::Move(-1, 1)),
+    ("down", Action::Move(0, 1)),
+    ("down_right", Action::Move(1, 1)),
+    ("teleport", Action::Teleport),
+    ("safe_teleport", Action::SafeTeleport),
+    ("bomb", Action::Bomb),
+    ("wait_for_end", Action::WaitForEnd),
+    ("wait", Action::Wait),
+    ("undo", Action::Undo),
+    ("quit", Action::Quit),
+];
+
+/// Keys with a name, and the short label the side panel shows for them.
+const NAMED_KEYS: [(&str, KeyCode, &str); 13] = [
+    ("up", KeyCode::Up, "Up"),
+    ("down", KeyCode::Down, "Down"),
+    ("left", KeyCode::Left, "Left"),
+    ("right", KeyCode::Right, "Right"),
+    ("home", KeyCode::Home, "Home"),
+    ("end", KeyCode::End, "End"),
+    ("pageup", KeyCode::PageUp, "PgUp"),
+    ("pagedown", KeyCode::PageDown, "PgDn"),
+    ("space", KeyCode::Char(' '), "Space"),
+    ("enter", KeyCode::Enter, "Enter"),
+    ("esc", KeyCode::Esc, "Esc"),
+    ("tab", KeyCode::Tab, "Tab"),
+    ("backspace", KeyCode::Backspace, "Bksp"),
+];
+
+pub struct Keymap {
+    /// The keys of every action, in the order of `ACTIONS`
+    keys: Vec<Vec<KeyCode>>,
+}
+
+impl Keymap {
+    pub fn new(layout: Layout) -> Keymap {
+        let letters = match layout {
+            Layout::Vi => [
+                "y",
+                "k",
+                "u",
+                "h",
+                "l",
+                "b",
+                "j",
+                "n",
+                "t",
+                "s",
+                "a",
+                "w",
+                ".",
+                "backspace",
+                "q",
+            ],
+            Layout::Numpad => [
+                "7",
+                "8",
+                "9",
+                "4",
+                "6",
+                "1",
+                "2",
+                "3",
+                "t",
+                "s",
+                "a",
+                "w",
+                "5",
+                "backspace",
+                "q",
+            ],
+            Layout::Wasd => [
+                "q",
+                "w",
+                "e",
+                "a",
+                "d",
+                "z",
+                "x",
+                "c",
+                "t",
+                "r",
+                "f",
+                "g",
+                "s",
+                "backspace",
+                "esc",
+            ],
+        };
+        // The arrow keys and the keys around them move the player in every layout
+        let navigation = [
+            KeyCode::Home,
+            KeyCode::Up,
+            KeyCode::PageUp,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::End,
+            KeyCode::Down,
+            KeyCode::PageDown,
+        ];
+
+        let mut keys: Vec<Vec<KeyCode>> = letters
+            .iter()
+            .map(|key| vec![parse_key(key).unwrap()])
+            .collect();
+        for (keys, key) in keys.iter_mut().zip(navigation) {
+            keys.push(key);
+        }
+        if layout == Layout::Numpad {
+            // The numpad has no room for the dot, but it is still a natural key to wait with
+            keys[index(Action::Wait)].push(KeyCode::Char('.'));
+        }
+        Keymap { keys }
+    }
+
+    /// Binds the actions listed in a keymap file to their new keys.
+    pub fn load(mut self, path: &str) -> Result<Keymap, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read keymap file {}: {}", path, err))?;
+        for (line, number) in content.lines().zip(1..) {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = || format!("Malformed line {} in keymap file {}", number, path);
+            let (name, value) = line.split_once('=').ok_or_else(malformed)?;
+            let index = ACTIONS
+                .iter()
+                .position(|(action, _)| *action == name.trim())
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown action {} on line {} in keymap file {}",
+                        name.trim(),
+                        number,
+                        path
+                    )
+                })?;
+            // Spaces around a key are trimmed, unless the key is the space itself
+            let keys = value
+                .split(';')
+                .map(|key| {
+                    let key = if key.trim().is_empty() {
+                        key
+                    } else {
+                        key.trim()
+                    };
+                    parse_key(key).ok_or_else(|| {
+                        format!(
+                            "Unknown key {} on line {} in keymap file {}",
+                            key, number, path
+                        )
+                    })
+                })
+                .collect::<Result<Vec<KeyCode>, String>>()?;
+            self.keys[index] = keys;
+        }
+
+        // Every key can only do one thing
+        for (index, keys) in self.keys.iter().enumerate() {
+            for key in keys {
+                if let Some(other) = (0..index).find(|other| self.keys[*other].contains(key)) {
+                    return Err(format!(
+                        "Key {} is bound to both {} and {} in keymap file {}",
+                        label(*key),
+                        ACTIONS[other].0,
+                        ACTIONS[index].0,
+                        path
+                    ));
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        let index = self.keys.iter().position(|keys| keys.contains(&key))?;
+        Some(ACTIONS[index].1)
+    }
+
+    /// The label of the first key of an action, or an empty label if it has no keys.
+    pub fn label(&self, action: Action) -> String {
+        self.keys[index(action)]
+            .first()
+            .map_or(String::new(), |key| label(*key))
+    }
+}
+
+fn index(action: Action) -> usize {
+    ACTIONS
+        .iter()
+        .position(|(_, other)| *other == action)
+        .expect("Every action has a place in the keymap")
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    let name = name.to_lowercase();
+    if let Some((_, key, _)) = NAMED_KEYS.iter().find(|(other, _, _)| *other == name) {
+        return Some(*key);
+    }
+    let number = name.strip_prefix('f')?.parse::<u8>().ok()?;
+    if (1..=12).contains(&number) {
+        Some(KeyCode::F(number))
+    } else {
+        None
+    }
+}
+
+fn label(key: KeyCode) -> String {
+    if let Some((_, _, label)) = NAMED_KEYS.iter().find(|(_, other, _)| *other == key) {
+        return label.to_string();
+    }
+    match key {
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(number) => format!("F{}", number),
+        _ => "?".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loads a keymap file on top of the vi layout
+    fn load(name: &str, content: &str) -> Result<Keymap, String> {
+        let path = std::env::temp_dir().join(format!("robots-{}-{}", std::process::id(), name));
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, content).unwrap();
+        let keymap = Keymap::new(Layout::Vi).load(&path);
+        fs::remove_file(&path).unwrap();
+        keymap
+    }
+
+    #[test]
+    fn keys_are_parsed_by_name() {
+        assert_eq!(parse_key("x"), Some(KeyCode::Char('x')));
+        assert_eq!(parse_key(" "), Some(KeyCode::Char(' ')));
+        assert_eq!(parse_key("Space"), Some(KeyCode::Char(' ')));
+        assert_eq!(parse_key("pagedown"), Some(KeyCode::PageDown));
+        assert_eq!(parse_key("F12"), Some(KeyCode::F(12)));
+        assert_eq!(parse_key("f13"), None);
+        assert_eq!(parse_key("shift"), None);
+    }
+
+    #[test]
+    fn a_keymap_file_replaces_the_keys_of_an_action() {
+        let keymap = load(
+            "keymap",
+            "# Bombs are too far away\n\nbomb = x ; f1\nwait=space\n",
+        )
+        .unwrap();
+        assert_eq!(keymap.action(KeyCode::Char('x')), Some(Action::Bomb));
+        assert_eq!(keymap.action(KeyCode::F(1)), Some(Action::Bomb));
+        assert_eq!(keymap.action(KeyCode::Char('a')), None);
+        assert_eq!(keymap.action(KeyCode::Char(' ')), Some(Action::Wait));
+        assert_eq!(keymap.action(KeyCode::Char('.')), None);
+        assert_eq!(keymap.label(Action::Bomb), "x");
+        assert_eq!(keymap.label(Action::Wait), "Space");
+        assert_eq!(keymap.action(KeyCode::Up), Some(Action::Move(0, -1)));
+    }
+
+    #[test]
+    fn a_key_can_only_do_one_thing() {
+        let err = load("duplicate", "bomb=t\n").err().unwrap();
+        assert!(
+            err.starts_with("Key t is bound to both teleport and bomb"),
+            "{}",
+            err
+        );
+        let err = load("duplicate-in-line", "bomb=x\nwait=z;x\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("Key x is bound to both bomb and wait"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn malformed_keymap_files_are_refused() {
+        let err = load("no-key", "bomb\n").err().unwrap();
+        assert!(err.starts_with("Malformed line 1"), "{}", err);
+        let err = load("unknown-action", "\nfly=x\n").err().unwrap();
+        assert!(err.starts_with("Unknown action fly on line 2"), "{}", err);
+        let err = load("unknown-key", "bomb=shift\n").err().unwrap();
+        assert!(err.starts_with("Unknown key shift on line 1"), "{}", err);
+    }
+}
@@ -1,31 +1,37 @@
 use crate::display::*;
+use crate::engine::*;
+use crate::frame::Screen;
 use crate::highscore::*;
-/// This module contains the logic for the game.
+use crate::keymap::Keymap;
+use crate::replay::Replay;
+use crate::savegame::*;
+use crate::stats::print_stats;
+/// This module contains the terminal driver for the game.
 ///
-/// It includes functions for player input, moving the player, handling game ticks,
-/// and updating the game state based on player actions.
-/// The module also includes a function for quitting the game and a function for
-/// handling the bombing action.
+/// The rules themselves live in the `engine` module. This module reads the players keys,
+/// turns them into `Action`s for the `Engine`, and hands the resulting `GameEvent`s and the new
+/// game state to the `display` module.
 ///
 /// The `player_input` function enables raw mode for reading player input events.
-/// It processes the key events and updates the player's position accordingly.
-/// The function returns a tuple indicating whether the move was legal and if the player wants to quit the game.
+/// It translates the key into an `Action` with the keymap, or `None` if the key has no meaning.
 ///
-/// The `move_player` function updates the player's position based on the given direction.
-/// It checks if the new position is within the game board boundaries and if it is a valid move.
-/// If the move is not valid, the function returns false.
+/// The `game_loop` function runs the game until the player is caught or quits, and then
+/// records the highscore and asks whether the player wants to try again. In practice mode the
+/// player can undo the move that got them caught instead. The screen is redrawn
+/// when the terminal is resized, and while the terminal is too small for the board the game
+/// waits for it to grow.
 ///
-/// The `game_tick` function handles the game tick, which is called periodically.
-/// It clears the game board, adds junk heaps to the board, and checks if the player wants to bomb.
-/// If the player wants to bomb, it calculates the bomb coordinates and updates the game state accordingly.
-/// The function also moves the dumb robots towards the player and handles collisions with junk heaps and the player.
-/// It updates the game board and the player's score accordingly.
+/// Whenever a level is cleared, a summary of it is shown before the next one starts.
+///
+/// The `replay_loop` function plays back a recorded game, with keys to pause, step and
+/// change the speed of the playback.
 use crate::structs::*;
+use crate::theme::Theme;
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
-    event::{read, Event, KeyCode},
+    event::{poll, read, Event, KeyCode},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode},
+    terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType},
 };
 use rand::Rng;
 use std::io;
@@ -34,815 +40,457 @@ fn quit_now() {
     // Just a clean up function
     execute!(io::stdout(), Show).unwrap();
     // Exit the application
-    println!("");
+    println!();
     std::process::exit(0);
 }
 
-fn player_input(
-    player: &mut Player, robots: &Vec<DumbRobot>, gamestate: &mut GameState,
-    game_board_data: &Vec<Vec<i32>>,
-) -> (bool, bool) {
+fn player_input(keymap: &Keymap) -> Option<Action> {
     enable_raw_mode().expect("Failed to enable raw mode");
 
-    let mut legal_move = false;
-    let mut quit = false;
+    let mut action = None;
 
-    match read().expect("Failed to read event") {
-        Event::Key(event) => {
-            match event.code {
-                KeyCode::Char(c) => {
-                    match c {
-                        'y' => legal_move = move_player(player, -1, -1, game_board_data), // Move diagonally up and left
-                        'k' => legal_move = move_player(player, 0, -1, game_board_data),  // Move up
-                        'u' => legal_move = move_player(player, 1, -1, game_board_data), // Move diagonally up and right
-                        'h' => legal_move = move_player(player, -1, 0, game_board_data), // Move left
-                        'l' => legal_move = move_player(player, 1, 0, game_board_data), // Move right,
-                        'b' => legal_move = move_player(player, -1, 1, game_board_data), // Move diagonally down and left
-                        'j' => legal_move = move_player(player, 0, 1, game_board_data), // Nove down
-                        'n' => legal_move = move_player(player, 1, 1, game_board_data), // Move diagonally down and right
-                        'q' => {
-                            player.is_alive = false;
-                            legal_move = false;
-                            quit = true;
-                        } // Quit the game (needs function)
-                        's' => {
-                            teleport_player(true, player, robots.clone());
-                            legal_move = true;
-                        } // Safe teleport
-                        't' => {
-                            teleport_player(false, player, robots.clone());
-                            legal_move = true;
-                        } // Teleport
-                        'a' => {
-                            gamestate.bomb_away = true;
-                            legal_move = true;
-                        } // Bomb
-                        'w' => {
-                            gamestate.wait_for_end = true;
-                            legal_move = true;
-                        } // Wait until robots are gone, or player is dead
-                        '.' => legal_move = true,                                       // Wait
-                        _ => legal_move = false, // Do nothing
-                    }
-                }
-                _ => (),
-            }
-        }
-        _ => {}
+    if let Event::Key(event) = read().expect("Failed to read event") {
+        action = keymap.action(event.code);
     }
 
     disable_raw_mode().expect("Failed to disable raw mode");
-    gamestate.turn += 1;
-    (legal_move, quit)
+    action
 }
 
-fn move_player(
-    player: &mut Player, d_pos_x: i32, d_pos_y: i32, game_board_data: &Vec<Vec<i32>>,
-) -> bool {
-    player.pos_x += d_pos_x;
-    player.pos_y += d_pos_y;
-
-    if player.pos_x < 1 {
-        player.pos_x = 1;
-    }
-    if player.pos_x >= BOARD_WIDTH {
-        player.pos_x = BOARD_WIDTH;
-    }
-    if player.pos_y < 1 {
-        player.pos_y = 1;
-    }
-    if player.pos_y >= BOARD_HEIGHT {
-        player.pos_y = BOARD_HEIGHT;
+// Shows the too small screen and waits for the next event. Only quitting is possible until the
+// terminal is large enough again.
+fn too_small_input(screen: &mut Screen, keymap: &Keymap, size: BoardSize) -> Option<Action> {
+    draw_too_small(screen, keymap, size);
+    enable_raw_mode().expect("Failed to enable raw mode");
+    let event = read().expect("Failed to read event");
+    disable_raw_mode().expect("Failed to disable raw mode");
+    match event {
+        Event::Key(event) if keymap.action(event.code) == Some(Action::Quit) => Some(Action::Quit),
+        _ => None,
     }
+}
 
-    if game_board_data[player.pos_y as usize - 1][player.pos_x as usize - 1] != 0 {
-        player.pos_x -= d_pos_x;
-        player.pos_y -= d_pos_y;
-        return false;
+// Waits until the board fits in the terminal, unless the player quits first. Quitting is left
+// to the caller, which knows what has to be saved.
+fn wait_for_room(screen: &mut Screen, keymap: &Keymap, size: BoardSize) -> Option<Action> {
+    while !terminal_fits(size) {
+        let action = too_small_input(screen, keymap, size);
+        if action.is_some() {
+            execute!(io::stdout(), Clear(ClearType::All), MoveTo(0, 0)).unwrap();
+            return action;
+        }
     }
-
-    true
+    None
 }
 
-fn game_tick(
-    player: &mut Player, dumb_robots: &mut Vec<DumbRobot>, junk_heaps: &mut Vec<JunkHeap>,
-    game_board_data: &mut Vec<Vec<i32>>, item: &mut Item, game_state: &mut GameState,
-) {
-    // Clear the game board..
-    game_board_data
-        .iter_mut()
-        .for_each(|row| row.iter_mut().for_each(|cell| *cell = 0));
-
-    // Add the junk heaps to the board as 2
-    for junk in junk_heaps.clone() {
-        game_board_data[junk.pos_y as usize - 1][junk.pos_x as usize - 1] = 2;
+pub fn run_game(args: &Args) {
+    match &args.command {
+        Some(Command::Highscore(query)) => return print_highscores(&args.path, query),
+        Some(Command::Stats(query)) => return print_stats(&args.path, &query.user, query.mode),
+        None => (),
     }
-
-    // Check if we should bomb away
-    if game_state.bomb_away {
-        game_state.bomb_away = false;
-        player.bombs -= 1;
-        // Here we should create a vector with all coordinates based on the player position in the following form
-        // ..B..   Space two steps up
-        // .BBB.   Space diagonal up and left, up and right, and up
-        // BB@BB   Two spaces left, one space left, two spaces right, one space right
-        // .BBB.   Space diagonal down and left, down and right, and down
-        // ..B..   Space two steps down
-        let bomb_coordinates = vec![
-            (player.pos_x, player.pos_y - 2),
-            (player.pos_x - 1, player.pos_y - 1),
-            (player.pos_x + 1, player.pos_y - 1),
-            (player.pos_x, player.pos_y - 1),
-            (player.pos_x - 2, player.pos_y),
-            (player.pos_x - 1, player.pos_y),
-            (player.pos_x + 1, player.pos_y),
-            (player.pos_x + 2, player.pos_y),
-            (player.pos_x - 1, player.pos_y + 1),
-            (player.pos_x + 1, player.pos_y + 1),
-            (player.pos_x, player.pos_y + 1),
-            (player.pos_x, player.pos_y + 2),
-        ];
-
-        // For all these coordinates, we should move to the position, as long as is in the playing field
-        // and draw a {
-        for coordinate in bomb_coordinates {
-            if coordinate.0 > 0
-                && coordinate.0 <= BOARD_WIDTH
-                && coordinate.1 > 0
-                && coordinate.1 <= BOARD_HEIGHT
-            {
-                // Check if there is a robot at this position
-                for robot in &mut *dumb_robots {
-                    if robot.pos_x == coordinate.0 && robot.pos_y == coordinate.1 {
-                        robot.is_scrap = true;
-                        player.score += 1;
-
-                        // Add a junk heap the heaps array
-                        junk_heaps.push(JunkHeap {
-                            pos_x: robot.pos_x,
-                            pos_y: robot.pos_y,
-                        });
-                    }
-                }
-
-                // Move the cursor to the specific position and print a {
-                move_cursor_padded(coordinate.0, coordinate.1);
-                println!("{{");
+    let theme = load_theme(args);
+    let keymap = load_keymap(args);
+    execute!(io::stdout(), Hide).unwrap();
+    if let Some(path) = &args.replay {
+        match Replay::load(path) {
+            Ok(replay) => replay_loop(&replay, &theme, &keymap),
+            Err(err) => {
+                eprintln!("{}", err);
+                execute!(io::stdout(), Show).unwrap();
+                std::process::exit(1);
             }
         }
-        // Wait for 500 ms
-        std::thread::sleep(std::time::Duration::from_millis(500));
     }
-
-    // All dumb_robots should move towards the player in a straight line
-    for robot in &mut *dumb_robots {
-        if robot.kind == 1 {
-            if !robot.is_scrap {
-                // First just make sure that this robot is not standing on a junk pile.
-                if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
-                    robot.is_scrap = true;
-                    player.score += 1;
-                    continue;
-                }
-
-                let old_x = robot.pos_x.clone();
-                let old_y = robot.pos_y.clone();
-
-                if robot.pos_x < player.pos_x {
-                    robot.pos_x += 1;
-                } else if robot.pos_x > player.pos_x {
-                    robot.pos_x -= 1;
-                }
-                if robot.pos_y < player.pos_y {
-                    robot.pos_y += 1;
-                } else if robot.pos_y > player.pos_y {
-                    robot.pos_y -= 1;
-                }
-                if robot.pos_y == player.pos_y && robot.pos_x == player.pos_x {
-                    if player.invincible {
-                        //robot.is_scrap = true;
-                        robot.pos_x = old_x;
-                        robot.pos_y = old_y;
-                        junk_heaps.push(JunkHeap {
-                            pos_x: robot.pos_x,
-                            pos_y: robot.pos_y,
-                        });
-                        player.score += 1;
-                        player.invincible = false;
-                    } else {
-                        player.is_alive = false;
-                    }
-                }
-
-                // Add this robot to the game_board if it is a free slot, otherwise turn into scrap
-                if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 0 {
-                    game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] = 1;
-                } else if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 1 {
-                    robot.is_scrap = true;
-                    player.score += 2;
-
-                    // Add a junk heap the heaps array
-                    junk_heaps.push(JunkHeap {
-                        pos_x: robot.pos_x,
-                        pos_y: robot.pos_y,
-                    });
-                } else if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
-                    robot.is_scrap = true;
-                    player.score += 1;
+    handle_highscore(args);
+    if let Some(path) = &args.resume {
+        match load_game(path) {
+            Ok((engine, replay)) => {
+                // A saved game can only be resumed once
+                if let Err(err) = std::fs::remove_file(path) {
+                    eprintln!("Couldn't remove saved game {}: {}", path, err);
                 }
+                game_loop(args, &theme, &keymap, engine, replay);
             }
-        } else if robot.kind == 2 {
-            if !robot.is_scrap {
-                // First just make sure that this robot is not standing on a junk pile.
-                if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
-                    robot.is_scrap = true;
-                    player.score += 1;
-                    continue;
-                }
-
-                // Check if the distance to player.x or player.y is less than 1
-                if (robot.pos_x - player.pos_x).abs() <= 1
-                    && (robot.pos_y - player.pos_y).abs() <= 1
-                {
-                    if player.invincible {
-                        robot.is_scrap = true;
-                        junk_heaps.push(JunkHeap {
-                            pos_x: robot.pos_x,
-                            pos_y: robot.pos_y,
-                        });
-                        player.score += 1;
-                        player.invincible = false;
-                    } else {
-                        player.is_alive = false;
-                    }
-                }
-
-                // The horse robot can move two steps forward and one to the side
-                let moves: Vec<(i32, i32)> = vec![
-                    (2, 1),
-                    (2, -1),
-                    (-2, 1),
-                    (-2, -1),
-                    (1, 2),
-                    (1, -2),
-                    (-1, 2),
-                    (-1, -2),
-                ];
-
-                // Iterate over the moves vector and calculate the euclidian distance to the player using the euclidian distance
-                let mut shortest_distance = 1000;
-                let mut shortest_move = (0, 0);
-
-                for candidate_move in moves {
-                    let new_x = robot.pos_x + candidate_move.0;
-                    let new_y = robot.pos_y + candidate_move.1;
-
-                    // Continue if the new position is outside the board
-                    if new_x < 1 || new_x > BOARD_WIDTH || new_y < 1 || new_y > BOARD_HEIGHT {
-                        continue;
-                    }
-
-                    // Calculate the distance to the player based on the current move. If it is the shortest move, update shortest move
-                    let distance = eucledian_distance(
-                        player,
-                        &DumbRobot {
-                            pos_x: new_x,
-                            pos_y: new_y,
-                            is_scrap: false,
-                            kind: 3,
-                        },
-                    );
-
-                    if distance <= shortest_distance {
-                        shortest_move = candidate_move;
-                        shortest_distance = distance;
-                    }
-                }
-
-                // Move the robot to the shortest move
-                if player.is_alive {
-                    robot.pos_x += shortest_move.0;
-                    robot.pos_y += shortest_move.1;
-                }
-
-                if robot.pos_y == player.pos_y && robot.pos_x == player.pos_x {
-                    if player.invincible {
-                        // For horses we will just randomize a direction for x and y where we should put the pile
-                        let mut rng = rand::thread_rng();
-                        let pile_x = rng.gen_range(-1..2);
-                        let pile_y = rng.gen_range(-1..2);
-                        junk_heaps.push(JunkHeap {
-                            pos_x: player.pos_x + pile_x,
-                            pos_y: robot.pos_y + pile_y,
-                        });
-                        player.score += 1;
-                        player.invincible = false;
-                    } else {
-                        player.is_alive = false;
-                    }
-                }
-
-                // Add this robot to the game_board if it is a free slot, otherwise turn into scrap
-                if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 0 {
-                    game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] = 1;
-                } else if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 1 {
-                    robot.is_scrap = true;
-                    player.score += 2;
-
-                    // Add a junk heap the heaps array
-                    junk_heaps.push(JunkHeap {
-                        pos_x: robot.pos_x,
-                        pos_y: robot.pos_y,
-                    });
-                } else if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
-                    robot.is_scrap = true;
-                    player.score += 1;
-                }
-            }
-        } else if robot.kind == 3 {
-            // This robot moves like a queen in chess. It should try to reduce the distance to the player with every move
-            if !robot.is_scrap {
-                // First just make sure that this robot is not standing on a junk pile.
-                if game_board_data[robot.pos_y as usize - 1][robot.pos_x as usize - 1] == 2 {
-                    robot.is_scrap = true;
-                    player.score += 1;
-                    continue;
-                }
-
-                let moves: Vec<(i32, i32)> = vec![
-                    (0, 1),
-                    (0, -1),
-                    (1, 0),
-                    (-1, 0),
-                    (1, 1),
-                    (-1, 1),
-                    (1, -1),
-                    (-1, -1),
-                ];
-
-                // Iterate over the moves vector and calculate the euclidian distance to the player using the euclidian distance
-                let mut shortest_distance = 1000;
-                let mut shortest_move = (0, 0);
-
-                for candidate_move in moves {
-                    let new_x = robot.pos_x + candidate_move.0;
-                    let new_y = robot.pos_y + candidate_move.1;
-
-                    // Continue if the new position is outside the board
-                    if new_x < 1 || new_x > BOARD_WIDTH || new_y < 1 || new_y > BOARD_HEIGHT {
-                        continue;
-                    }
-
-                    // Calculate the distance to the player based on the current move. If it is the shortest move, update shortest move
-                    let distance = eucledian_distance(
-                        player,
-                        &DumbRobot {
-                            pos_x: new_x,
-                            pos_y: new_y,
-                            is_scrap: false,
-                            kind: 3,
-                        },
-                    );
-
-                    if distance <= shortest_distance {
-                        shortest_move = candidate_move;
-                        shortest_distance = distance;
-                    }
-                }
-
-                // We now have a unit vector in which direction to move. Loop this move until you either hit the
-                // players x_position and/or y_position
-                let mut new_x = robot.pos_x;
-                let mut new_y = robot.pos_y;
-
-                let mut keep_moving = true;
-
-                while keep_moving {
-                    new_x += shortest_move.0;
-                    new_y += shortest_move.1;
-
-                    // Check that new new position is withint the board
-                    if new_x < 1 || new_x > BOARD_WIDTH || new_y < 1 || new_y > BOARD_HEIGHT {
-                        break;
-                    }
-
-                    if new_x == player.pos_x && new_y == player.pos_y {
-                        if player.invincible {
-                            //robot.is_scrap = true;
-                            junk_heaps.push(JunkHeap {
-                                pos_x: robot.pos_x,
-                                pos_y: robot.pos_y,
-                            });
-                            player.score += 1;
-                            player.invincible = false;
-                        } else {
-                            robot.pos_x = new_x;
-                            robot.pos_y = new_y;
-                            player.is_alive = false;
-                        }
-                    }
-
-                    // Check the board for this position to make sure that it is a free spot
-                    if game_board_data[new_y as usize - 1][new_x as usize - 1] == 0 {
-                        robot.pos_x = new_x;
-                        robot.pos_y = new_y;
-                    } else if game_board_data[new_y as usize - 1][new_x as usize - 1] == 1 {
-                        robot.is_scrap = true;
-                        player.score += 2;
-
-                        // Add a junk heap the heaps array
-                        junk_heaps.push(JunkHeap {
-                            pos_x: robot.pos_x,
-                            pos_y: robot.pos_y,
-                        });
-                    } else if game_board_data[new_y as usize - 1][new_x as usize - 1] == 2 {
-                        robot.is_scrap = true;
-                        player.score += 1;
-                    }
-
-                    if player.pos_x == robot.pos_x || player.pos_y == robot.pos_y && player.is_alive
-                    {
-                        keep_moving = false;
-                    }
-                }
+            Err(err) => {
+                eprintln!("{}", err);
+                execute!(io::stdout(), Show).unwrap();
+                std::process::exit(1);
             }
         }
     }
+    // Show the splash
+    splash_screen();
+    let (engine, replay) = new_game(args);
+    game_loop(args, &theme, &keymap, engine, replay);
+}
 
-    // See whether we should show the current level item
-    // It should be a 2 percent chance of showing the item
-    let mut rng = rand::thread_rng();
-    let show_item = rng.gen_range(1..100);
-    if show_item <= 5 && !item.visible {
-        item.visible = true;
-    }
-
-    // Also make sure that the player is not standing on a newly created junk pile..
-    if game_board_data[player.pos_y as usize - 1][player.pos_x as usize - 1] != 0 {
-        player.is_alive = false;
+fn load_keymap(args: &Args) -> Keymap {
+    let keymap = Keymap::new(args.layout);
+    match &args.keymap {
+        Some(path) => keymap.load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }),
+        None => keymap,
     }
+}
 
-    // Check if the player is standing on the item
-    if player.pos_x == item.pos_x && player.pos_y == item.pos_y && item.visible && !item.picked_up {
-        item.picked_up = true;
-        if item.kind == 1 {
-            player.invincible = true;
-        } else if item.kind == 2 {
-            player.bombs += 1;
-        }
+fn load_theme(args: &Args) -> Theme {
+    let theme = Theme::new(args.theme, args.glyphs);
+    match &args.theme_file {
+        Some(path) => theme.load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }),
+        None => theme,
     }
 }
 
-fn eucledian_distance(player: &Player, robot: &DumbRobot) -> i32 {
-    // Calculate the Eucledian distance between the player and the robot
-    // ((player.pos_x as f64 - robot.pos_x as f64).powi(2) + (player.pos_y as f64 - robot.pos_y as f64).powi(2)).sqrt() as i32
-    (player.pos_x - robot.pos_x).abs() + (player.pos_y - robot.pos_y).abs()
+fn new_game(args: &Args) -> (Engine, Replay) {
+    // Use the requested seed, or pick a fresh one so the game can still be shared afterwards
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+    // Generates the first level, with robots based on the level and a random player position
+    let size = board_size(args);
+    let mut engine = Engine::new(
+        &args.username,
+        seed,
+        size,
+        args.classic,
+        args.teleporting_robot_level,
+    );
+    engine.practice = args.practice;
+    let replay = Replay::new(
+        &args.username,
+        seed,
+        size,
+        args.classic,
+        args.teleporting_robot_level,
+    );
+    (engine, replay)
 }
 
-fn teleport_player(try_safe: bool, player: &mut Player, dumb_robots: Vec<DumbRobot>) {
-    // Because Andreas said so.. We need a prompt to tell people that they are teleporting..
-
-    let mut safe_teleport = false;
-    // Check if the player has any safe teleports left
-    if player.safe_teleports > 0 && try_safe {
-        safe_teleport = true;
-        player.safe_teleports -= 1;
-    }
-
-    execute!(
-        io::stdout(),
-        MoveTo(
-            BOARD_WIDTH as u16 + 7,
-            PADDING_TOP as u16 + BOARD_HEIGHT as u16
-        )
-    )
-    .unwrap();
-    if safe_teleport {
-        print!("Teleporting (safe)...");
-    } else {
-        print!("Teleporting...");
+// The size of the board for a new game, either as given or as large as the terminal allows
+fn board_size(args: &Args) -> BoardSize {
+    if !args.fit {
+        return BoardSize {
+            width: args.width,
+            height: args.height,
+        };
     }
+    let (columns, rows) = terminal::size().unwrap_or((0, 0));
+    fitting_board_size(columns, rows)
+}
 
-    println!("");
-
-    // Sleep for 200ms
-    std::thread::sleep(std::time::Duration::from_millis(500));
-
-    let mut rng = rand::thread_rng();
-    let mut new_x = rng.gen_range(1..BOARD_WIDTH);
-    let mut new_y = rng.gen_range(1..BOARD_HEIGHT);
-
-    // Is this a safe teleport?
-    if safe_teleport {
-        let mut safe_location = false;
-        while !safe_location {
-            safe_location = true;
-            // Check that the distance to a robot to a safe spot is at least 2
-            for robot in &dumb_robots {
-                if (robot.pos_x - new_x).abs() < 2 && (robot.pos_y - new_y).abs() < 2 {
-                    safe_location = false;
-                }
+fn game_loop(args: &Args, theme: &Theme, keymap: &Keymap, mut engine: Engine, mut replay: Replay) {
+    let mut screen = Screen::new();
+    let try_again = loop {
+        while engine.player.is_alive {
+            // A resize shows up as an input without an action, after which everything is redrawn
+            let fits = terminal_fits(engine.size);
+            if fits {
+                draw_boundaries(&mut screen, theme, keymap, &engine);
+                draw_active_objects(
+                    &mut screen,
+                    theme,
+                    &engine.player,
+                    &engine.robots,
+                    &engine.junk_heaps,
+                    &engine.item,
+                    engine.death.as_ref(),
+                );
+                screen.present();
             }
-            if !safe_location {
-                new_x = rng.gen_range(1..BOARD_WIDTH);
-                new_y = rng.gen_range(1..BOARD_HEIGHT);
+            if !fits || !engine.state.wait_for_end {
+                let action = if fits {
+                    player_input(keymap)
+                } else {
+                    too_small_input(&mut screen, keymap, engine.size)
+                };
+                if action == Some(Action::Quit) {
+                    quit_game(args, &mut engine, &mut replay);
+                }
+                if let Some(action) = action {
+                    let events = engine.apply(action);
+                    record(&mut replay, action, &events);
+                    draw_events(&mut screen, theme, engine.size, &events);
+                    if level_summary(&mut screen, theme, keymap, &engine, &events).is_some() {
+                        quit_game(args, &mut engine, &mut replay);
+                    }
+                }
+            } else {
+                let events = engine.tick();
+                draw_events(&mut screen, theme, engine.size, &events);
+                if level_summary(&mut screen, theme, keymap, &engine, &events).is_some() {
+                    quit_game(args, &mut engine, &mut replay);
+                }
+                // Sleep for 75ms
+                std::thread::sleep(std::time::Duration::from_millis(75));
             }
         }
-        player.pos_x = new_x;
-        player.pos_y = new_y;
-    } else {
-        player.pos_x = new_x;
-        player.pos_y = new_y;
-    }
-    enable_raw_mode().expect("Failed to enable raw mode");
-}
 
-fn any_robots_left(robots: &Vec<DumbRobot>) -> bool {
-    // Iterate over the robots and check whether at least one is alive
-    for robot in robots {
-        if !robot.is_scrap {
-            return true;
+        // All is over.. Let the robots make their final move and present the retry prompt..
+        engine.tick();
+        // In practice mode the game is only over once the player no longer wants to undo
+        let can_undo = engine.can_undo();
+        if !can_undo {
+            add_highscore(args, &engine);
+            save_replay(args, &replay);
         }
-    }
 
-    false
-}
+        let mut first_time = true;
+        let answer = loop {
+            // Quitting is just like not trying again
+            if wait_for_room(&mut screen, keymap, engine.size).is_some() {
+                break Retry::No;
+            }
+            draw_boundaries(&mut screen, theme, keymap, &engine);
+            draw_active_objects(
+                &mut screen,
+                theme,
+                &engine.player,
+                &engine.robots,
+                &engine.junk_heaps,
+                &engine.item,
+                engine.death.as_ref(),
+            );
+
+            let caught = death_message(engine.death.as_ref());
+            print_padded(&mut screen, 4, 0, &caught);
+            draw_panel_line(
+                &mut screen,
+                engine.size,
+                3,
+                &format!("Seed:  {}", engine.seed),
+            );
+
+            if first_time {
+                screen.present();
+                // Sleep for 1000ms
+                std::thread::sleep(std::time::Duration::from_millis(1000));
+                first_time = false;
+            }
 
-fn no_of_dumb_robots(level: i32) -> i32 {
-    if level < 2 {
-        20
-    } else {
-        let robots = 20 + (level - 2) * 5;
-        robots
-    }
-}
+            if let Some(answer) = retry_query(&mut screen, engine.size, keymap, can_undo) {
+                break answer;
+            }
+        };
 
-fn no_of_super_robots(level: i32) -> i32 {
-    if level < 5 {
-        0
-    } else {
-        let robots = 1 + (level - 4) * 2;
-        robots
-    }
-}
+        if answer == Retry::Undo {
+            let events = engine.apply(Action::Undo);
+            record(&mut replay, Action::Undo, &events);
+            continue;
+        }
+        if can_undo {
+            add_highscore(args, &engine);
+            save_replay(args, &replay);
+        }
+        break answer == Retry::Yes;
+    };
 
-fn no_of_killer_robots(level: i32) -> i32 {
-    if level < 9 {
-        0
+    if try_again {
+        let (engine, replay) = new_game(args);
+        game_loop(args, theme, keymap, engine, replay);
     } else {
-        let robots = 1 + ((level - 10) * 2) + 1;
-        robots
+        show_highscore(&args.path, engine.mode(), &engine.player, &engine.state);
+        quit_now();
     }
 }
 
-// Generate level
-fn generate_level(
-    gamestate: &GameState, game_board_data: &mut Vec<Vec<i32>>, dumb_robots: &mut Vec<DumbRobot>,
-    junk_heaps: &mut Vec<JunkHeap>, player: &mut Player, item: &mut Item,
-) {
-    let mut rng = rand::thread_rng();
-
-    // Clear the game board..
-    game_board_data
-        .iter_mut()
-        .for_each(|row| row.iter_mut().for_each(|cell| *cell = 0));
-
-    // Add one additional safe teleport per level
-    player.safe_teleports += 1;
-    player.invincible = false;
-
-    // Clear the old junk piles vector and the dumb_robots one
-    dumb_robots.clear();
-    junk_heaps.clear();
-
-    // Update the level drop item
-    item.pos_x = rng.gen_range(1..BOARD_WIDTH);
-    item.pos_y = rng.gen_range(1..BOARD_HEIGHT);
-    item.level = gamestate.level;
-    item.kind = rng.gen_range(1..3);
-    item.visible = false;
-    item.picked_up = false;
-
-    // Add dumb robots.
-    for _ in 0..no_of_dumb_robots(gamestate.level) {
-        let mut occupied = true;
-        let mut p_x = 0;
-        let mut p_y = 0;
-
-        while occupied {
-            p_x = rng.gen_range(1..BOARD_WIDTH);
-            p_y = rng.gen_range(1..BOARD_HEIGHT);
-
-            if game_board_data[p_y as usize - 1][p_x as usize - 1] == 0 {
-                occupied = false;
-            }
+// Shows the summary of the level that was just cleared, if any, until a key is pressed. The
+// player may quit instead while the terminal is too small.
+fn level_summary(
+    screen: &mut Screen, theme: &Theme, keymap: &Keymap, engine: &Engine, events: &[GameEvent],
+) -> Option<Action> {
+    let summary = events
+        .iter()
+        .find(|event| matches!(event, GameEvent::LevelCleared { .. }))?;
+    loop {
+        if let Some(action) = wait_for_room(screen, keymap, engine.size) {
+            return Some(action);
         }
+        draw_boundaries(screen, theme, keymap, engine);
+        draw_level_summary(
+            screen,
+            theme,
+            engine,
+            summary,
+            "(Press any key to continue...)",
+        );
+        screen.present();
 
-        dumb_robots.push(DumbRobot {
-            pos_x: p_x,
-            pos_y: p_y,
-            is_scrap: false,
-            kind: 1,
-        });
-        // Add the robot to the game board array
-        game_board_data[p_y as usize - 1][p_x as usize - 1] = 1;
-    }
-
-    // Add super robots.
-    for _ in 0..no_of_super_robots(gamestate.level) {
-        let mut occupied = true;
-        let mut p_x = 0;
-        let mut p_y = 0;
-
-        while occupied {
-            p_x = rng.gen_range(1..BOARD_WIDTH);
-            p_y = rng.gen_range(1..BOARD_HEIGHT);
-
-            if game_board_data[p_y as usize - 1][p_x as usize - 1] == 0 {
-                occupied = false;
-            }
+        enable_raw_mode().expect("Failed to enable raw mode");
+        let event = read().expect("Failed to read event");
+        disable_raw_mode().expect("Failed to disable raw mode");
+        if let Event::Key(_) = event {
+            return None;
         }
-
-        dumb_robots.push(DumbRobot {
-            pos_x: p_x,
-            pos_y: p_y,
-            is_scrap: false,
-            kind: 2,
-        });
-        // Add the robot to the game board array
-        game_board_data[p_y as usize - 1][p_x as usize - 1] = 1;
     }
+}
 
-    // Add killer robots.
-    for _ in 0..no_of_killer_robots(gamestate.level) {
-        let mut occupied = true;
-        let mut p_x = 0;
-        let mut p_y = 0;
-
-        while occupied {
-            p_x = rng.gen_range(1..BOARD_WIDTH);
-            p_y = rng.gen_range(1..BOARD_HEIGHT);
-
-            if game_board_data[p_y as usize - 1][p_x as usize - 1] == 0 {
-                occupied = false;
-            }
-        }
-
-        dumb_robots.push(DumbRobot {
-            pos_x: p_x,
-            pos_y: p_y,
-            is_scrap: false,
-            kind: 3,
-        });
-        // Add the robot to the game board array
-        game_board_data[p_y as usize - 1][p_x as usize - 1] = 1;
+// Adds the action to the recording, or takes the last one out again when it was undone
+fn record(replay: &mut Replay, action: Action, events: &[GameEvent]) {
+    if events.contains(&GameEvent::Undone) {
+        replay.actions.pop();
+    } else if action != Action::Undo && !events.contains(&GameEvent::Blocked) {
+        // Blocked moves change nothing, and have nothing to take back
+        replay.actions.push(action);
     }
+}
 
-    // Setup the player
-    let mut found_starting_spot = false;
-    let mut p_x = 0;
-    let mut p_y = 0;
-
-    while !found_starting_spot {
-        p_x = rng.gen_range(1..BOARD_WIDTH);
-        p_y = rng.gen_range(1..BOARD_HEIGHT);
-
-        if game_board_data[p_y as usize - 1][p_x as usize - 1] == 0 {
-            found_starting_spot = true;
+fn save_replay(args: &Args, replay: &Replay) {
+    if let Some(path) = &args.record {
+        if let Err(err) = replay.save(path) {
+            eprintln!("Couldn't write replay file {}: {}", path, err);
         }
     }
-
-    player.pos_x = p_x;
-    player.pos_y = p_y;
 }
 
-pub fn run_game(args: &Args) {
-    execute!(io::stdout(), Hide).unwrap();
-    handle_highscore(&args);
-    // Show the splash
-    splash_screen();
-    game_loop(args);
+// Gives up the game, after saving it and its recording when asked to
+fn quit_game(args: &Args, engine: &mut Engine, replay: &mut Replay) {
+    save_on_quit(args, engine, replay);
+    let events = engine.apply(Action::Quit);
+    record(replay, Action::Quit, &events);
+    save_replay(args, replay);
+    quit_now();
 }
 
-fn game_loop(args: &Args) {
-    let mut game_board_data: Vec<Vec<i32>> =
-        vec![vec![0; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
-
-    let mut dumb_robots: Vec<DumbRobot> = Vec::new();
-    let mut junk_heaps: Vec<JunkHeap> = Vec::new();
-
-    // Basic setup
-    let mut gamestate = GameState {
-        turn: 0,
-        level: 1,
-        wait_for_end: false,
-        bomb_away: false,
-    };
-
-    let mut player = Player {
-        username: args.username.to_string(),
-        score: 0,
-        is_alive: true,
-        pos_x: 0,
-        pos_y: 0,
-        safe_teleports: 2,
-        invincible: false,
-        bombs: 0,
-    };
+fn save_on_quit(args: &Args, engine: &Engine, replay: &Replay) {
+    if let Some(path) = &args.save {
+        if let Err(err) = save_game(path, engine, replay) {
+            eprintln!("Couldn't save the game to {}: {}", path, err);
+        }
+    }
+}
 
-    let mut item = Item {
-        pos_x: 0,
-        pos_y: 0,
-        level: 0,
-        kind: 0,
-        visible: false,
-        picked_up: false,
-    };
+fn replay_loop(replay: &Replay, theme: &Theme, keymap: &Keymap) {
+    let mut engine = Engine::new(
+        &replay.username,
+        replay.seed,
+        replay.size,
+        replay.classic,
+        replay.teleporting_robot_level,
+    );
+    let mut actions = replay.actions.iter();
+    let mut delay = 300;
+    let mut paused = false;
+    let mut quit = false;
+    let mut screen = Screen::new();
 
-    while player.is_alive {
-        // Generate level should generate robots based on the level, and randomize the player position
-        generate_level(
-            &gamestate,
-            &mut game_board_data,
-            &mut dumb_robots,
-            &mut junk_heaps,
-            &mut player,
-            &mut item,
+    while engine.player.is_alive {
+        if wait_for_room(&mut screen, keymap, engine.size).is_some() {
+            quit_now();
+        }
+        draw_boundaries(&mut screen, theme, keymap, &engine);
+        draw_active_objects(
+            &mut screen,
+            theme,
+            &engine.player,
+            &engine.robots,
+            &engine.junk_heaps,
+            &engine.item,
+            engine.death.as_ref(),
         );
-
-        while player.is_alive && any_robots_left(&dumb_robots) {
-            draw_boundaries(&player, &gamestate, &junk_heaps, &dumb_robots);
-            draw_active_objects(&player, &dumb_robots, &junk_heaps, &item);
-            if !gamestate.wait_for_end {
-                let (legal_move, quit) =
-                    player_input(&mut player, &dumb_robots, &mut gamestate, &game_board_data);
-                if legal_move {
-                    game_tick(
-                        &mut player,
-                        &mut dumb_robots,
-                        &mut junk_heaps,
-                        &mut game_board_data,
-                        &mut item,
-                        &mut gamestate,
-                    );
-                }
-                if quit {
-                    quit_now();
+        draw_replay_status(
+            &mut screen,
+            engine.size,
+            replay.actions.len() - actions.len(),
+            replay.actions.len(),
+            delay,
+            paused,
+        );
+        screen.present();
+
+        // Wait for the next step, unless the viewer wants something else. When paused we only
+        // move on when asked to.
+        let mut step = false;
+        enable_raw_mode().expect("Failed to enable raw mode");
+        if paused || poll(std::time::Duration::from_millis(delay)).expect("Failed to poll event") {
+            if let Event::Key(event) = read().expect("Failed to read event") {
+                match event.code {
+                    KeyCode::Char(' ') => paused = !paused, // Pause or resume
+                    KeyCode::Char('n') => step = true,      // Step forward one turn
+                    KeyCode::Char('+') => delay = (delay / 2).max(25), // Fast forward
+                    KeyCode::Char('-') => delay = (delay * 2).min(2000), // Slow down
+                    KeyCode::Char('q') => {
+                        disable_raw_mode().expect("Failed to disable raw mode");
+                        quit_now();
+                    }
+                    _ => (),
                 }
-            } else {
-                game_tick(
-                    &mut player,
-                    &mut dumb_robots,
-                    &mut junk_heaps,
-                    &mut game_board_data,
-                    &mut item,
-                    &mut gamestate,
-                );
-                // Sleep for 75ms
-                std::thread::sleep(std::time::Duration::from_millis(75));
             }
+        } else {
+            step = true;
+        }
+        disable_raw_mode().expect("Failed to disable raw mode");
+        if !step {
+            continue;
+        }
 
-            if !any_robots_left(&dumb_robots) {
-                gamestate.wait_for_end = false;
-
-                // Increase the level (and perhaps write something)
-                gamestate.level += 1;
-                generate_level(
-                    &gamestate,
-                    &mut game_board_data,
-                    &mut dumb_robots,
-                    &mut junk_heaps,
-                    &mut player,
-                    &mut item,
-                );
-            }
+        let events = if engine.state.wait_for_end {
+            engine.tick()
+        } else if let Some(action) = actions.next() {
+            engine.apply(*action)
+        } else {
+            break;
+        };
+        quit = events.contains(&GameEvent::Quit);
+        draw_events(&mut screen, theme, engine.size, &events);
+        // Keep the summary of a cleared level up for a while, without waiting for the viewer
+        if let Some(summary) = events
+            .iter()
+            .find(|event| matches!(event, GameEvent::LevelCleared { .. }))
+        {
+            draw_boundaries(&mut screen, theme, keymap, &engine);
+            draw_level_summary(&mut screen, theme, &engine, summary, "");
+            screen.present();
+            std::thread::sleep(std::time::Duration::from_millis(delay * 5));
         }
     }
 
-    // All is over.. Present the retry prompt..
-    game_tick(
-        &mut player,
-        &mut dumb_robots,
-        &mut junk_heaps,
-        &mut game_board_data,
-        &mut item,
-        &mut gamestate,
-    );
-    draw_boundaries(&player, &gamestate, &junk_heaps, &dumb_robots);
-    draw_active_objects(&player, &dumb_robots, &junk_heaps, &item);
-
-    move_cursor_padded(4, 0);
-    println!("[You did not make it. You were caught by the robots..]");
-    add_highscore(&args, &player, &gamestate);
+    // Just like the game, let the robots make their final move if the player was caught
+    if !engine.player.is_alive && !quit {
+        engine.tick();
+    }
+    // Redraw the final position until a key is pressed, so it survives a resize
+    loop {
+        if wait_for_room(&mut screen, keymap, engine.size).is_some() {
+            quit_now();
+        }
+        draw_boundaries(&mut screen, theme, keymap, &engine);
+        draw_active_objects(
+            &mut screen,
+            theme,
+            &engine.player,
+            &engine.robots,
+            &engine.junk_heaps,
+            &engine.item,
+            engine.death.as_ref(),
+        );
 
-    // Sleep for 1000ms
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+        let end = format!("[End of the replay of {}]", replay.username);
+        print_padded(&mut screen, 4, 0, &end);
+        draw_panel_line(
+            &mut screen,
+            engine.size,
+            3,
+            &format!("Seed:  {}", replay.seed),
+        );
+        draw_panel_line(
+            &mut screen,
+            engine.size,
+            2,
+            "(Press any key to continue...)",
+        );
+        screen.present();
 
-    if retry_query() {
-        game_loop(args);
-    } else {
-        show_highscore(&args.path, &player, &gamestate);
-        quit_now();
-        std::process::exit(0);
+        enable_raw_mode().expect("Failed to enable raw mode");
+        let event = read().expect("Failed to read event");
+        disable_raw_mode().expect("Failed to disable raw mode");
+        if let Event::Key(_) = event {
+            break;
+        }
     }
+    quit_now();
 }
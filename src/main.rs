@@ -1,7 +1,16 @@
 mod display;
+mod engine;
+mod frame;
 mod highscore;
+mod keymap;
 mod logic;
+mod replay;
+mod rng;
+mod robot_kind;
+mod savegame;
+mod stats;
 mod structs;
+mod theme;
 
 use crate::logic::*;
 use crate::structs::*;
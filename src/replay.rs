@@ -0,0 +1,227 @@
+/// Module for recording and replaying games.
+///
+/// Since the engine is fully determined by its seed, a game can be stored as the seed plus the
+/// list of actions the player took. The replay file is a small text file with one `key=value`
+/// pair per line, where the actions are written as the classic one letter commands. Actions
+/// that were undone in practice mode are left out, since undoing also rewinds the random
+/// number generator.
+///
+/// A replay only plays out the same game under the rules it was recorded with, so the version
+/// goes up whenever the rules change what a seed and a list of actions lead to, and replays of
+/// older versions are refused. Version 2 added the size of the board, version 3 whether the game
+/// was played by the classic rules, and version 4 came with robots that all move at the same
+/// time. Version 5 added the teleporting robots and the level they start at, version 6 the
+/// armoured robots and version 7 the junk pushers.
+use crate::engine::Action;
+use crate::structs::*;
+use std::fs;
+use std::io;
+
+const REPLAY_VERSION: u32 = 7;
+
+pub struct Replay {
+    pub username: String,
+    pub seed: u64,
+    pub size: BoardSize,
+    pub classic: bool,
+    pub teleporting_robot_level: i32,
+    pub actions: Vec<Action>,
+}
+
+impl Replay {
+    pub fn new(
+        username: &str, seed: u64, size: BoardSize, classic: bool, teleporting_robot_level: i32,
+    ) -> Replay {
+        Replay {
+            username: username.to_string(),
+            seed,
+            size,
+            classic,
+            teleporting_robot_level,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let actions: String = self.actions.iter().map(|action| encode(*action)).collect();
+        let content = format!(
+            "version={}\nusername={}\nseed={}\nwidth={}\nheight={}\nclassic={}\nteleporting_robot_level={}\nactions={}\n",
+            REPLAY_VERSION,
+            self.username,
+            self.seed,
+            self.size.width,
+            self.size.height,
+            self.classic,
+            self.teleporting_robot_level,
+            actions
+        );
+        fs::write(path, content)
+    }
+
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read replay file {}: {}", path, err))?;
+
+        let mut version = None;
+        let mut username = None;
+        let mut seed = None;
+        let mut width = None;
+        let mut height = None;
+        let mut classic = None;
+        let mut teleporting_robot_level = None;
+        let mut actions = None;
+        for line in content.lines() {
+            match line.split_once('=') {
+                Some(("version", value)) => version = value.parse::<u32>().ok(),
+                Some(("username", value)) => username = Some(value.to_string()),
+                Some(("seed", value)) => seed = value.parse::<u64>().ok(),
+                Some(("width", value)) => width = value.parse::<i32>().ok(),
+                Some(("height", value)) => height = value.parse::<i32>().ok(),
+                Some(("classic", value)) => classic = value.parse::<bool>().ok(),
+                Some(("teleporting_robot_level", value)) => {
+                    teleporting_robot_level = value.parse::<i32>().ok().filter(|level| *level >= 1)
+                }
+                Some(("actions", value)) => {
+                    actions = value.chars().map(decode).collect::<Option<Vec<Action>>>()
+                }
+                _ => (),
+            }
+        }
+
+        match version {
+            Some(REPLAY_VERSION) => (),
+            Some(version) if version < REPLAY_VERSION => {
+                return Err(format!(
+                    "Replay file {} was recorded with older rules of the game, and can't be played back",
+                    path
+                ))
+            }
+            _ => return Err(format!("Unsupported replay file version in {}", path)),
+        }
+        let size = match (width, height) {
+            (Some(width), Some(height))
+                if width >= MIN_BOARD_WIDTH && height >= MIN_BOARD_HEIGHT =>
+            {
+                Some(BoardSize { width, height })
+            }
+            _ => None,
+        };
+        match (
+            username,
+            seed,
+            size,
+            classic,
+            teleporting_robot_level,
+            actions,
+        ) {
+            (
+                Some(username),
+                Some(seed),
+                Some(size),
+                Some(classic),
+                Some(teleporting_robot_level),
+                Some(actions),
+            ) => Ok(Replay {
+                username,
+                seed,
+                size,
+                classic,
+                teleporting_robot_level,
+                actions,
+            }),
+            _ => Err(format!("Malformed replay file {}", path)),
+        }
+    }
+}
+
+pub fn encode(action: Action) -> char {
+    match action {
+        Action::Move(-1, -1) => 'y',
+        Action::Move(0, -1) => 'k',
+        Action::Move(1, -1) => 'u',
+        Action::Move(-1, 0) => 'h',
+        Action::Move(1, 0) => 'l',
+        Action::Move(-1, 1) => 'b',
+        Action::Move(0, 1) => 'j',
+        Action::Move(1, 1) => 'n',
+        Action::Move(_, _) => unreachable!("The player only moves one step at a time"),
+        Action::Teleport => 't',
+        Action::SafeTeleport => 's',
+        Action::Bomb => 'a',
+        Action::WaitForEnd => 'w',
+        Action::Wait => '.',
+        Action::Undo => unreachable!("Undone actions are taken out of the recording"),
+        Action::Quit => 'q',
+    }
+}
+
+pub fn decode(key: char) -> Option<Action> {
+    match key {
+        'y' => Some(Action::Move(-1, -1)),
+        'k' => Some(Action::Move(0, -1)),
+        'u' => Some(Action::Move(1, -1)),
+        'h' => Some(Action::Move(-1, 0)),
+        'l' => Some(Action::Move(1, 0)),
+        'b' => Some(Action::Move(-1, 1)),
+        'j' => Some(Action::Move(0, 1)),
+        'n' => Some(Action::Move(1, 1)),
+        't' => Some(Action::Teleport),
+        's' => Some(Action::SafeTeleport),
+        'a' => Some(Action::Bomb),
+        'w' => Some(Action::WaitForEnd),
+        '.' => Some(Action::Wait),
+        'q' => Some(Action::Quit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot_kind::TELEPORTING_ROBOT_LEVEL;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("robots-{}-{}", std::process::id(), name));
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn actions_are_written_as_the_classic_commands() {
+        for key in "ykuhlbjntsaw.q".chars() {
+            let action = decode(key).unwrap();
+            assert_eq!(encode(action), key);
+        }
+        assert_eq!(decode('y'), Some(Action::Move(-1, -1)));
+        assert_eq!(decode('.'), Some(Action::Wait));
+        assert_eq!(decode('x'), None);
+    }
+
+    #[test]
+    fn a_recording_survives_the_round_trip() {
+        let size = BoardSize {
+            width: 30,
+            height: 15,
+        };
+        let mut replay = Replay::new("alice", 42, size, true, TELEPORTING_ROBOT_LEVEL);
+        replay.actions = vec![Action::Move(1, 0), Action::Teleport, Action::Wait];
+        let path = temp_path("replay");
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.username, "alice");
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.size, size);
+        assert!(loaded.classic);
+        assert_eq!(loaded.actions, replay.actions);
+    }
+
+    #[test]
+    fn recordings_of_older_rules_are_refused() {
+        let path = temp_path("older");
+        let content = "version=3\nusername=alice\nseed=42\nwidth=60\nheight=24\nclassic=false\nactions=hjkl\n";
+        fs::write(&path, content).unwrap();
+        let err = Replay::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("older rules of the game"), "{}", err);
+    }
+}
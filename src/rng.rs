@@ -0,0 +1,61 @@
+/// The random number generator used by the engine.
+///
+/// It wraps a seeded `StdRng` and counts how many numbers have been drawn from it. That count
+/// together with the seed is the complete state of the generator, which makes it possible to
+/// save a game and later continue it with exactly the same random decisions.
+use rand::{rngs::StdRng, Error, RngCore, SeedableRng};
+
+#[derive(Clone)]
+pub struct GameRng {
+    rng: StdRng,
+    draws: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        GameRng {
+            rng: StdRng::seed_from_u64(seed),
+            draws: 0,
+        }
+    }
+
+    /// Recreates a generator that has already handed out `draws` numbers.
+    pub fn restore(seed: u64, draws: u64) -> GameRng {
+        let mut rng = GameRng::new(seed);
+        for _ in 0..draws {
+            rng.next_u32();
+        }
+        rng
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+// Everything is drawn through `next_u32`, so the number of draws alone tells how far along
+// the generator is.
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let low = self.next_u32() as u64;
+        let high = self.next_u32() as u64;
+        (high << 32) | low
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
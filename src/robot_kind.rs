@@ -0,0 +1,215 @@
+/// Module for the kinds of robots and how they behave.
+///
+/// Every kind of robot is described in one place: its name, the glyphs it is drawn with, how
+/// many of them a level has, what scrapping one is worth, and how it moves towards the player.
+/// The engine, the theme, the side panel legend and the level summary go through
+/// `RobotKind::ALL`, so a new kind of robot only has to be added here (and, if it shouldn't have
+/// the colours of the terminal, to the colour tables of the themes).
+use crate::structs::BoardSize;
+
+/// The first level with teleporting robots, unless another one is given on the command line.
+pub const TELEPORTING_ROBOT_LEVEL: i32 = 7;
+
+/// The number of turns a teleporting robot walks before it teleports again.
+pub const TELEPORT_INTERVAL: i32 = 4;
+
+/// How many rows and columns away from the player a teleporting robot may land.
+pub const TELEPORT_RANGE: i32 = 4;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RobotKind {
+    /// Steps straight towards the player, like in the classic game
+    Robot,
+    /// Jumps like a knight in chess, and catches the player from the next cell
+    SuperRobot,
+    /// Slides like a queen in chess, until it is in line with the player
+    KillerRobot,
+    /// Steps like a plain robot, but every few turns teleports to somewhere near the player
+    TeleportingRobot,
+    /// Steps like a plain robot, and survives its first crash as one
+    ArmouredRobot,
+    /// Steps like a plain robot, and pushes the junk heaps it runs into
+    JunkPusher,
+}
+
+impl RobotKind {
+    pub const ALL: [RobotKind; 6] = [
+        RobotKind::Robot,
+        RobotKind::SuperRobot,
+        RobotKind::KillerRobot,
+        RobotKind::TeleportingRobot,
+        RobotKind::ArmouredRobot,
+        RobotKind::JunkPusher,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RobotKind::Robot => "robot",
+            RobotKind::SuperRobot => "super robot",
+            RobotKind::KillerRobot => "killer robot",
+            RobotKind::TeleportingRobot => "teleporting robot",
+            RobotKind::ArmouredRobot => "armoured robot",
+            RobotKind::JunkPusher => "junk pusher",
+        }
+    }
+
+    /// The number of the kind in save files.
+    pub fn id(&self) -> i32 {
+        match self {
+            RobotKind::Robot => 1,
+            RobotKind::SuperRobot => 2,
+            RobotKind::KillerRobot => 3,
+            RobotKind::TeleportingRobot => 4,
+            RobotKind::ArmouredRobot => 5,
+            RobotKind::JunkPusher => 6,
+        }
+    }
+
+    pub fn from_id(id: i32) -> Option<RobotKind> {
+        RobotKind::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    /// The ASCII and the Unicode glyph the board shows it as.
+    pub fn glyphs(&self) -> (char, char) {
+        match self {
+            RobotKind::Robot => ('+', '●'),
+            RobotKind::SuperRobot => ('&', '◆'),
+            RobotKind::KillerRobot => ('N', '▲'),
+            RobotKind::TeleportingRobot => ('T', '◐'),
+            RobotKind::ArmouredRobot => ('A', '■'),
+            RobotKind::JunkPusher => ('P', '◧'),
+        }
+    }
+
+    /// The points for scrapping one, which are the same for every kind. Crashing it into another
+    /// robot is worth twice as much.
+    pub fn score(&self) -> i32 {
+        1
+    }
+
+    /// Whether the classic game has this kind of robot.
+    pub fn classic(&self) -> bool {
+        *self == RobotKind::Robot
+    }
+
+    /// How many of them a level has on the standard board, when teleporting robots show up from
+    /// `teleporting_robot_level` on.
+    pub fn count(&self, level: i32, teleporting_robot_level: i32) -> i32 {
+        match self {
+            RobotKind::Robot if level < 2 => 20,
+            RobotKind::Robot => 20 + (level - 2) * 5,
+            RobotKind::SuperRobot if level < 5 => 0,
+            RobotKind::SuperRobot => 1 + (level - 4) * 2,
+            RobotKind::KillerRobot if level < 9 => 0,
+            RobotKind::KillerRobot => 1 + ((level - 10) * 2) + 1,
+            RobotKind::TeleportingRobot if level < teleporting_robot_level => 0,
+            RobotKind::TeleportingRobot => 1 + (level - teleporting_robot_level),
+            RobotKind::ArmouredRobot if level < 10 => 0,
+            RobotKind::ArmouredRobot => 1 + (level - 10),
+            RobotKind::JunkPusher if level < 12 => 0,
+            RobotKind::JunkPusher => 1 + (level - 12),
+        }
+    }
+
+    /// Whether it catches a player who stands right next to it, before it even moves.
+    pub fn catches_nearby(&self) -> bool {
+        *self == RobotKind::SuperRobot
+    }
+
+    /// Whether it teleports near the player every `TELEPORT_INTERVAL` turns.
+    pub fn teleports(&self) -> bool {
+        *self == RobotKind::TeleportingRobot
+    }
+
+    /// Whether its armour saves it from its first crash into junk or another robot, after which
+    /// it is a plain robot.
+    pub fn armoured(&self) -> bool {
+        *self == RobotKind::ArmouredRobot
+    }
+
+    /// Whether it pushes a junk heap it runs into one cell further, instead of being wrecked by
+    /// it.
+    pub fn pushes_junk(&self) -> bool {
+        *self == RobotKind::JunkPusher
+    }
+
+    /// The cells the robot passes through on its way to the player this turn, the last one being
+    /// where it ends up. It stops early when it runs into something on the way.
+    pub fn path(&self, from: (i32, i32), player: (i32, i32), size: BoardSize) -> Vec<(i32, i32)> {
+        match self {
+            RobotKind::Robot
+            | RobotKind::TeleportingRobot
+            | RobotKind::ArmouredRobot
+            | RobotKind::JunkPusher => {
+                let step = ((player.0 - from.0).signum(), (player.1 - from.1).signum());
+                vec![(from.0 + step.0, from.1 + step.1)]
+            }
+            RobotKind::SuperRobot => {
+                // The knight move that gets closest to the player
+                let moves = [
+                    (2, 1),
+                    (2, -1),
+                    (-2, 1),
+                    (-2, -1),
+                    (1, 2),
+                    (1, -2),
+                    (-1, 2),
+                    (-1, -2),
+                ];
+                closest_move(&moves, from, player, size)
+                    .map_or(Vec::new(), |step| vec![(from.0 + step.0, from.1 + step.1)])
+            }
+            RobotKind::KillerRobot => {
+                // Slide in the direction that gets closest to the player, until the robot is
+                // in the same row or column as the player, or at the edge of the board
+                let moves = [
+                    (0, 1),
+                    (0, -1),
+                    (1, 0),
+                    (-1, 0),
+                    (1, 1),
+                    (-1, 1),
+                    (1, -1),
+                    (-1, -1),
+                ];
+                let Some(step) = closest_move(&moves, from, player, size) else {
+                    return Vec::new();
+                };
+                let mut path = Vec::new();
+                let mut cell = (from.0 + step.0, from.1 + step.1);
+                while size.contains(cell.0, cell.1) {
+                    path.push(cell);
+                    if cell.0 == player.0 || cell.1 == player.1 {
+                        break;
+                    }
+                    cell = (cell.0 + step.0, cell.1 + step.1);
+                }
+                path
+            }
+        }
+    }
+}
+
+// Of the moves that stay on the board, the one that ends closest to the player. The last of
+// equally good moves wins.
+fn closest_move(
+    moves: &[(i32, i32)], from: (i32, i32), player: (i32, i32), size: BoardSize,
+) -> Option<(i32, i32)> {
+    let mut closest: Option<((i32, i32), i32)> = None;
+    for step in moves {
+        let cell = (from.0 + step.0, from.1 + step.1);
+        if !size.contains(cell.0, cell.1) {
+            continue;
+        }
+        let distance = distance(cell, player);
+        if closest.is_none_or(|(_, shortest)| distance <= shortest) {
+            closest = Some((*step, distance));
+        }
+    }
+    closest.map(|(step, _)| step)
+}
+
+// The number of steps along the rows and columns between two cells
+fn distance(a: (i32, i32), b: (i32, i32)) -> i32 {
+    (a.0 - b.0).abs() + (a.1 - b.1).abs()
+}
@@ -0,0 +1,346 @@
+/// Module for saving a game in progress and resuming it later.
+///
+/// The save file holds everything the engine needs to continue exactly where it stopped: the
+/// game state, the player, all robots and junk heaps, the level item, the board, the stats of
+/// the level so far and the state of the random number generator. The actions taken so far are
+/// stored as well, so a recording of the game can go on after it is resumed. A practice game
+/// stays a practice game, but the turns played before saving can't be undone anymore.
+///
+/// Like the replay file it is a text file with one `key=value` pair per line. Robots, junk
+/// heaps and board rows are repeated keys, and compound values are separated by `;`.
+use crate::engine::{Action, Engine};
+use crate::replay::{decode, encode, Replay};
+use crate::rng::GameRng;
+use crate::robot_kind::RobotKind;
+use crate::structs::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+const SAVE_VERSION: u32 = 11;
+
+pub fn save_game(path: &str, engine: &Engine, replay: &Replay) -> io::Result<()> {
+    let player = &engine.player;
+    let state = &engine.state;
+    let item = &engine.item;
+    let stats = &engine.level_stats;
+
+    let mut lines = vec![
+        format!("version={}", SAVE_VERSION),
+        format!("seed={}", engine.seed),
+        format!("rng={}", engine.rng.draws()),
+        format!("size={};{}", engine.size.width, engine.size.height),
+        format!("classic={}", engine.classic),
+        format!("teleporting_robot_level={}", engine.teleporting_robot_level),
+        format!("practice={}", engine.practice),
+        format!("username={}", player.username),
+        format!("score={}", player.score),
+        format!("position={};{}", player.pos_x, player.pos_y),
+        format!("safe_teleports={}", player.safe_teleports),
+        format!("invincible={}", player.invincible),
+        format!("bombs={}", player.bombs),
+        format!("turn={}", state.turn),
+        format!("level={}", state.level),
+        format!("wait_for_end={}", state.wait_for_end),
+        format!("scrapped_while_waiting={}", state.scrapped_while_waiting),
+        format!("bomb_away={}", state.bomb_away),
+        format!("robots_destroyed={}", state.robots_destroyed),
+        format!("bombs_used={}", state.bombs_used),
+        format!("teleports_used={}", state.teleports_used),
+        format!(
+            "item={};{};{};{};{};{}",
+            item.pos_x, item.pos_y, item.level, item.kind, item.visible, item.picked_up
+        ),
+        format!(
+            "level_stats={};{};{};{};{}",
+            stats.collisions, stats.bombed, stats.junked, stats.turns, stats.teleports
+        ),
+        format!(
+            "scrapped={}",
+            stats
+                .scrapped
+                .map(|scrapped| scrapped.to_string())
+                .join(";")
+        ),
+    ];
+    for robot in &engine.robots {
+        lines.push(format!(
+            "robot={};{};{};{};{};{}",
+            robot.pos_x,
+            robot.pos_y,
+            robot.is_scrap,
+            robot.kind.id(),
+            robot.turns,
+            robot.damaged
+        ));
+    }
+    for junk in &engine.junk_heaps {
+        lines.push(format!("junk={};{}", junk.pos_x, junk.pos_y));
+    }
+    for row in &engine.board {
+        let cells: String = row.iter().map(|cell| cell.to_string()).collect();
+        lines.push(format!("board={}", cells));
+    }
+    let actions: String = replay
+        .actions
+        .iter()
+        .map(|action| encode(*action))
+        .collect();
+    lines.push(format!("actions={}", actions));
+
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+pub fn load_game(path: &str) -> Result<(Engine, Replay), String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Couldn't read saved game {}: {}", path, err))?;
+    let malformed = || format!("Malformed saved game {}", path);
+
+    let mut values: HashMap<&str, &str> = HashMap::new();
+    let mut robots = Vec::new();
+    let mut junk_heaps = Vec::new();
+    let mut board = Vec::new();
+    for line in content.lines() {
+        let (key, value) = line.split_once('=').ok_or_else(malformed)?;
+        match key {
+            "robot" => {
+                let parts = split(value, 6).ok_or_else(malformed)?;
+                robots.push(DumbRobot {
+                    pos_x: parse(parts[0]).ok_or_else(malformed)?,
+                    pos_y: parse(parts[1]).ok_or_else(malformed)?,
+                    is_scrap: parse(parts[2]).ok_or_else(malformed)?,
+                    kind: parse(parts[3])
+                        .and_then(RobotKind::from_id)
+                        .ok_or_else(malformed)?,
+                    turns: parse(parts[4]).ok_or_else(malformed)?,
+                    damaged: parse(parts[5]).ok_or_else(malformed)?,
+                });
+            }
+            "junk" => {
+                let parts = split(value, 2).ok_or_else(malformed)?;
+                junk_heaps.push(JunkHeap {
+                    pos_x: parse(parts[0]).ok_or_else(malformed)?,
+                    pos_y: parse(parts[1]).ok_or_else(malformed)?,
+                });
+            }
+            "board" => {
+                let row = value
+                    .chars()
+                    .map(|cell| cell.to_digit(3).map(|cell| cell as i32))
+                    .collect::<Option<Vec<i32>>>()
+                    .ok_or_else(malformed)?;
+                board.push(row);
+            }
+            _ => {
+                values.insert(key, value);
+            }
+        }
+    }
+
+    let value = |key: &str| values.get(key).copied().ok_or_else(malformed);
+    let number = |key: &str| value(key).and_then(|value| parse(value).ok_or_else(malformed));
+    let flag = |key: &str| value(key).and_then(|value| parse(value).ok_or_else(malformed));
+
+    if number("version")? != SAVE_VERSION as i32 {
+        return Err(format!("Unsupported saved game version in {}", path));
+    }
+    let size = split(value("size")?, 2).ok_or_else(malformed)?;
+    let size = BoardSize {
+        width: parse(size[0]).ok_or_else(malformed)?,
+        height: parse(size[1]).ok_or_else(malformed)?,
+    };
+    if size.width < MIN_BOARD_WIDTH
+        || size.height < MIN_BOARD_HEIGHT
+        || board.len() != size.height as usize
+        || board.iter().any(|row| row.len() != size.width as usize)
+    {
+        return Err(malformed());
+    }
+
+    let seed: u64 = parse(value("seed")?).ok_or_else(malformed)?;
+    let draws: u64 = parse(value("rng")?).ok_or_else(malformed)?;
+    let position = split(value("position")?, 2).ok_or_else(malformed)?;
+    let item = split(value("item")?, 6).ok_or_else(malformed)?;
+    let stats = numbers(value("level_stats")?, 5).ok_or_else(malformed)?;
+    let scrapped = numbers(value("scrapped")?, RobotKind::ALL.len()).ok_or_else(malformed)?;
+    let actions = value("actions")?
+        .chars()
+        .map(decode)
+        .collect::<Option<Vec<Action>>>()
+        .ok_or_else(malformed)?;
+    let username = value("username")?.to_string();
+
+    let engine = Engine {
+        player: Player {
+            username: username.clone(),
+            is_alive: true,
+            score: number("score")?,
+            pos_x: parse(position[0]).ok_or_else(malformed)?,
+            pos_y: parse(position[1]).ok_or_else(malformed)?,
+            safe_teleports: number("safe_teleports")?,
+            invincible: flag("invincible")?,
+            bombs: number("bombs")?,
+        },
+        robots,
+        junk_heaps,
+        item: Item {
+            pos_x: parse(item[0]).ok_or_else(malformed)?,
+            pos_y: parse(item[1]).ok_or_else(malformed)?,
+            level: parse(item[2]).ok_or_else(malformed)?,
+            kind: parse(item[3]).ok_or_else(malformed)?,
+            visible: parse(item[4]).ok_or_else(malformed)?,
+            picked_up: parse(item[5]).ok_or_else(malformed)?,
+        },
+        state: GameState {
+            turn: number("turn")?,
+            level: number("level")?,
+            wait_for_end: flag("wait_for_end")?,
+            bomb_away: flag("bomb_away")?,
+            robots_destroyed: number("robots_destroyed")?,
+            bombs_used: number("bombs_used")?,
+            teleports_used: number("teleports_used")?,
+            scrapped_while_waiting: number("scrapped_while_waiting")?,
+        },
+        level_stats: LevelStats {
+            scrapped: scrapped.try_into().map_err(|_| malformed())?,
+            collisions: stats[0],
+            bombed: stats[1],
+            junked: stats[2],
+            turns: stats[3],
+            teleports: stats[4],
+        },
+        // Only games that are still going can be saved
+        death: None,
+        seed,
+        size,
+        board,
+        rng: GameRng::restore(seed, draws),
+        classic: flag("classic")?,
+        teleporting_robot_level: number("teleporting_robot_level")?,
+        practice: flag("practice")?,
+        history: Vec::new(),
+    };
+    // Everything has to be on the board, or the engine would look outside of it
+    let mut positions = [
+        (engine.player.pos_x, engine.player.pos_y),
+        (engine.item.pos_x, engine.item.pos_y),
+    ]
+    .into_iter()
+    .chain(engine.robots.iter().map(|robot| (robot.pos_x, robot.pos_y)))
+    .chain(
+        engine
+            .junk_heaps
+            .iter()
+            .map(|junk| (junk.pos_x, junk.pos_y)),
+    );
+    if positions.any(|(pos_x, pos_y)| !size.contains(pos_x, pos_y)) {
+        return Err(malformed());
+    }
+    let replay = Replay {
+        username,
+        seed,
+        size,
+        classic: engine.classic,
+        teleporting_robot_level: engine.teleporting_robot_level,
+        actions,
+    };
+    Ok((engine, replay))
+}
+
+fn split(value: &str, parts: usize) -> Option<Vec<&str>> {
+    let split: Vec<&str> = value.split(';').collect();
+    if split.len() == parts {
+        Some(split)
+    } else {
+        None
+    }
+}
+
+// A list of numbers, separated by `;`
+fn numbers(value: &str, count: usize) -> Option<Vec<i32>> {
+    split(value, count)?.into_iter().map(parse).collect()
+}
+
+fn parse<T: FromStr>(value: &str) -> Option<T> {
+    value.parse::<T>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot_kind::TELEPORTING_ROBOT_LEVEL;
+
+    const SIZE: BoardSize = BoardSize {
+        width: 30,
+        height: 15,
+    };
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("robots-{}-{}", std::process::id(), name));
+        path.to_string_lossy().to_string()
+    }
+
+    // A game a few turns in, and its recording so far
+    fn game() -> (Engine, Replay) {
+        let mut engine = Engine::new("alice", 7, SIZE, false, TELEPORTING_ROBOT_LEVEL);
+        let mut replay = Replay::new("alice", 7, SIZE, false, TELEPORTING_ROBOT_LEVEL);
+        for action in [Action::Teleport, Action::Wait, Action::SafeTeleport] {
+            engine.apply(action);
+            replay.actions.push(action);
+        }
+        (engine, replay)
+    }
+
+    #[test]
+    fn a_resumed_game_goes_on_where_it_was_saved() {
+        let (mut engine, replay) = game();
+        let path = temp_path("resume");
+        save_game(&path, &engine, &replay).unwrap();
+        let (mut resumed, resumed_replay) = load_game(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resumed_replay.actions, replay.actions);
+        assert_eq!(resumed.rng.draws(), engine.rng.draws());
+        assert_eq!(resumed.state.turn, 3);
+        // The random number generator was restored, so the same actions play out the same way
+        for action in [
+            Action::Teleport,
+            Action::Wait,
+            Action::Teleport,
+            Action::Wait,
+        ] {
+            assert_eq!(resumed.apply(action), engine.apply(action));
+            let position = (engine.player.pos_x, engine.player.pos_y);
+            assert_eq!((resumed.player.pos_x, resumed.player.pos_y), position);
+            assert_eq!(resumed.player.score, engine.player.score);
+            assert_eq!(resumed.board, engine.board);
+        }
+    }
+
+    #[test]
+    fn positions_outside_of_the_board_are_refused() {
+        let (engine, replay) = game();
+        let path = temp_path("outside");
+        save_game(&path, &engine, &replay).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        for key in ["position=", "robot="] {
+            // Move the first one of them off the left edge
+            let mut moved = false;
+            let lines: Vec<String> = content
+                .lines()
+                .map(|line| match line.split_once(';') {
+                    Some((_, rest)) if line.starts_with(key) && !moved => {
+                        moved = true;
+                        format!("{}0;{}", key, rest)
+                    }
+                    _ => line.to_string(),
+                })
+                .collect();
+            fs::write(&path, lines.join("\n")).unwrap();
+            let err = load_game(&path).err().unwrap();
+            assert!(err.starts_with("Malformed saved game"), "{}", err);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
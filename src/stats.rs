@@ -0,0 +1,224 @@
+/// Module for the per-player statistics report.
+///
+/// The statistics are computed from the highscore history of a single player, in a single mode,
+/// since classic and practice games are scored differently. Games that were
+/// recorded before the highscore file kept track of robots or teleports don't count towards
+/// those totals, and the report says how many games the totals are based on.
+use crate::highscore::{read_highscores, HighscoreRecord};
+use crate::structs::GameMode;
+use std::io::{self, prelude::*};
+
+/// How many of the latest scores to list in the report.
+const RECENT_SCORES: usize = 10;
+
+pub struct PlayerStats {
+    pub games_played: usize,
+    pub average_score: f64,
+    pub median_score: f64,
+    pub best_score: i32,
+    pub best_level: i32,
+    pub robots_scrapped: Total,
+    pub teleports_used: Total,
+    /// Average change in score from one game to the next, from a least squares fit
+    pub score_trend: f64,
+    /// The latest scores, oldest first
+    pub recent_scores: Vec<i32>,
+}
+
+/// A sum over the games that recorded the value.
+pub struct Total {
+    pub sum: i32,
+    pub games: usize,
+}
+
+impl Total {
+    fn of(values: impl Iterator<Item = Option<i32>>) -> Total {
+        let known: Vec<i32> = values.flatten().collect();
+        Total {
+            sum: known.iter().sum(),
+            games: known.len(),
+        }
+    }
+}
+
+/// Computes the statistics of a user in a mode, or `None` if they haven't played any games of it.
+pub fn player_stats(
+    highscores: &[HighscoreRecord], user: &str, mode: GameMode,
+) -> Option<PlayerStats> {
+    let mut games: Vec<&HighscoreRecord> = highscores
+        .iter()
+        .filter(|record| record.username == user && record.mode == mode.name())
+        .collect();
+    if games.is_empty() {
+        return None;
+    }
+    games.sort_by_key(|record| record.timestamp);
+
+    let scores: Vec<i32> = games.iter().map(|record| record.score).collect();
+    let games_played = scores.len();
+    let average_score = scores.iter().sum::<i32>() as f64 / games_played as f64;
+
+    let mut sorted = scores.clone();
+    sorted.sort();
+    let median_score = if games_played.is_multiple_of(2) {
+        (sorted[games_played / 2 - 1] + sorted[games_played / 2]) as f64 / 2.0
+    } else {
+        sorted[games_played / 2] as f64
+    };
+
+    // Least squares slope of the scores against the game number
+    let mean_game = (games_played - 1) as f64 / 2.0;
+    let mut covariance = 0.0;
+    let mut variance = 0.0;
+    for (game, score) in scores.iter().enumerate() {
+        covariance += (game as f64 - mean_game) * (*score as f64 - average_score);
+        variance += (game as f64 - mean_game).powi(2);
+    }
+    let score_trend = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+
+    Some(PlayerStats {
+        games_played,
+        average_score,
+        median_score,
+        best_score: *sorted.last().unwrap(),
+        best_level: games.iter().map(|record| record.level).max().unwrap(),
+        robots_scrapped: Total::of(games.iter().map(|record| record.robots_destroyed)),
+        teleports_used: Total::of(games.iter().map(|record| record.teleports_used)),
+        score_trend,
+        recent_scores: scores[games_played.saturating_sub(RECENT_SCORES)..].to_vec(),
+    })
+}
+
+fn format_total(total: &Total, games_played: usize) -> String {
+    if total.games == games_played {
+        total.sum.to_string()
+    } else {
+        format!(
+            "{} (recorded in {} of {} games)",
+            total.sum, total.games, games_played
+        )
+    }
+}
+
+fn format_stats(user: &str, mode: GameMode, stats: &PlayerStats) -> Vec<String> {
+    let recent: Vec<String> = stats
+        .recent_scores
+        .iter()
+        .map(|score| score.to_string())
+        .collect();
+    let rows = vec![
+        ("Games played", stats.games_played.to_string()),
+        ("Average score", format!("{:.1}", stats.average_score)),
+        ("Median score", format!("{:.1}", stats.median_score)),
+        ("Best score", stats.best_score.to_string()),
+        ("Best level", stats.best_level.to_string()),
+        (
+            "Robots scrapped",
+            format_total(&stats.robots_scrapped, stats.games_played),
+        ),
+        (
+            "Teleports used",
+            format_total(&stats.teleports_used, stats.games_played),
+        ),
+        (
+            "Score trend",
+            format!("{:+.1} points per game", stats.score_trend),
+        ),
+        ("Recent scores", recent.join(" ")),
+    ];
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    let mut result = vec![format!(" Statistics for {} ({} games):", user, mode.name())];
+    result.push(format!(" {}", "-".repeat(40)));
+    for (label, value) in rows {
+        result.push(format!(
+            " {:<width$}  {}",
+            format!("{}:", label),
+            value,
+            width = width + 1
+        ));
+    }
+    result.push(format!(" {}", "-".repeat(40)));
+    result
+}
+
+/// Prints the statistics report of a user, for the stats subcommand.
+pub fn print_stats(path: &str, user: &str, mode: GameMode) {
+    let content = match player_stats(&read_highscores(path), user, mode) {
+        Some(stats) => format_stats(user, mode, &stats).join("\n"),
+        None => format!(" No {} games found for {}", mode.name(), user),
+    };
+    if let Err(err) = writeln!(io::stdout(), "{}", content) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Couldn't print the statistics: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(username: &str, score: i32, timestamp: u64, teleports: Option<i32>) -> HighscoreRecord {
+        HighscoreRecord {
+            username: username.to_string(),
+            score,
+            level: score / 10,
+            timestamp,
+            seed: None,
+            turns: None,
+            robots_destroyed: Some(score / 2),
+            bombs_used: None,
+            teleports_used: teleports,
+            mode: "normal".to_string(),
+        }
+    }
+
+    #[test]
+    fn the_statistics_of_a_player() {
+        // Out of order, the trend goes by the time the games were played
+        let highscores = [
+            game("alice", 40, 4, Some(3)),
+            game("alice", 10, 1, None),
+            game("bob", 500, 2, Some(1)),
+            game("alice", 30, 3, Some(2)),
+            game("alice", 20, 2, Some(1)),
+        ];
+        let stats = player_stats(&highscores, "alice", GameMode::Normal).unwrap();
+        assert_eq!(stats.games_played, 4);
+        assert_eq!(stats.average_score, 25.0);
+        assert_eq!(stats.median_score, 25.0);
+        assert_eq!(stats.best_score, 40);
+        assert_eq!(stats.best_level, 4);
+        assert_eq!(stats.score_trend, 10.0);
+        assert_eq!(stats.recent_scores, [10, 20, 30, 40]);
+        assert_eq!(
+            (stats.teleports_used.sum, stats.teleports_used.games),
+            (6, 3)
+        );
+        assert_eq!(stats.robots_scrapped.sum, 50);
+
+        let stats = player_stats(&highscores[1..4], "alice", GameMode::Normal).unwrap();
+        assert_eq!(stats.median_score, 20.0);
+        assert_eq!(stats.score_trend, 20.0);
+
+        let stats = player_stats(&highscores[2..3], "bob", GameMode::Normal).unwrap();
+        assert_eq!(stats.median_score, 500.0);
+        assert_eq!(stats.score_trend, 0.0);
+    }
+
+    #[test]
+    fn only_the_games_of_the_player_in_the_mode_count() {
+        let mut practice = game("alice", 10, 1, None);
+        practice.mode = "practice".to_string();
+        let highscores = [practice, game("bob", 20, 2, None)];
+        assert!(player_stats(&highscores, "alice", GameMode::Normal).is_none());
+        assert!(player_stats(&highscores, "carol", GameMode::Normal).is_none());
+        let stats = player_stats(&highscores, "alice", GameMode::Practice).unwrap();
+        assert_eq!(stats.games_played, 1);
+    }
+}
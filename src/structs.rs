@@ -1,22 +1,46 @@
 /// This module contains the definitions of various structs used in the application.
 ///
-/// The `GameState` struct represents the state of the game, including the current turn, level, and other game-related flags.
+/// The `BoardSize` struct holds the width and height of the playing field, chosen at startup.
+///
+/// The `GameState` struct represents the state of the game, including the current turn, level, counters for the highscore, and other game-related flags.
+///
+/// The `LevelStats` struct counts what happened during the current level, for the summary that is shown once it is cleared.
 ///
 /// The `Player` struct represents a player in the game, with properties such as username, score, position, and abilities.
 ///
 /// The `Item` struct represents an item in the game, with properties such as position, level, kind, and visibility.
 ///
-/// The `DumbRobot` struct represents a dumb robot in the game, with properties such as position and kind.
+/// The `DumbRobot` struct represents a robot in the game, with properties such as position and its `RobotKind`.
 ///
 /// The `JunkHeap` struct represents a junk heap in the game, with properties such as position.
 ///
-/// The `Args` struct is used for parsing command line arguments using the `clap` crate. It contains properties such as username, path, and show_highscore flag.
-use clap::Parser;
+/// The `Args` struct is used for parsing command line arguments using the `clap` crate. It contains properties such as username, path, show_highscore flag, seed, board size, colour theme, key layout, glyphs, replay files, saved games, the practice and classic modes and the first level with teleporting robots.
+///
+/// The `Command` enum holds the subcommands, such as the `highscore` query described by `HighscoreQuery` and the `stats` report described by `StatsQuery`.
+///
+/// The `GameMode` enum tells the rules a game was played by, so the highscores and statistics of different modes are kept apart.
+use crate::robot_kind::{RobotKind, TELEPORTING_ROBOT_LEVEL};
+use clap::{Parser, Subcommand, ValueEnum};
 
 pub const PADDING_LEFT: i32 = 3;
 pub const PADDING_TOP: i32 = 1;
 pub const BOARD_WIDTH: i32 = 60;
 pub const BOARD_HEIGHT: i32 = 24;
+pub const MIN_BOARD_WIDTH: i32 = 20;
+pub const MIN_BOARD_HEIGHT: i32 = 10;
+
+/// The size of the playing field. Positions on the board run from 1 to the width and height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoardSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl BoardSize {
+    pub fn contains(&self, pos_x: i32, pos_y: i32) -> bool {
+        (1..=self.width).contains(&pos_x) && (1..=self.height).contains(&pos_y)
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct GameState {
@@ -24,8 +48,27 @@ pub struct GameState {
     pub level: i32,
     pub wait_for_end: bool,
     pub bomb_away: bool,
+    pub robots_destroyed: i32,
+    pub bombs_used: i32,
+    pub teleports_used: i32,
+    /// Robots scrapped since the player started waiting for the end of the level
+    pub scrapped_while_waiting: i32,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    /// Scrapped robots, in the order of `RobotKind::ALL`
+    pub scrapped: [i32; RobotKind::ALL.len()],
+    /// Robots scrapped by running into another robot, or into an invincible player
+    pub collisions: i32,
+    pub bombed: i32,
+    /// Robots scrapped by running into a junk heap
+    pub junked: i32,
+    pub turns: i32,
+    pub teleports: i32,
+}
+
+#[derive(Clone)]
 pub struct Player {
     pub username: String,
     pub is_alive: bool,
@@ -37,6 +80,7 @@ pub struct Player {
     pub bombs: i32,
 }
 
+#[derive(Clone)]
 pub struct Item {
     pub pos_x: i32,
     pub pos_y: i32,
@@ -51,7 +95,11 @@ pub struct DumbRobot {
     pub pos_x: i32,
     pub pos_y: i32,
     pub is_scrap: bool,
-    pub kind: i32,
+    pub kind: RobotKind,
+    /// The turns since the robot last teleported, for the kinds that do
+    pub turns: i32,
+    /// Whether it is an armoured robot that lost its armour, and is a plain robot now
+    pub damaged: bool,
 }
 
 #[derive(Clone)]
@@ -63,6 +111,9 @@ pub struct JunkHeap {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Username
     #[arg(short, long, default_value = "show_highscore")]
     pub username: String,
@@ -74,4 +125,181 @@ pub struct Args {
     /// Show highscore
     #[arg(short, long)]
     pub show_highscore: bool,
+
+    /// Seed for the random number generator, to replay a specific game
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Width of the board
+    #[arg(long, default_value_t = BOARD_WIDTH, value_parser = clap::value_parser!(i32).range(MIN_BOARD_WIDTH as i64..=400))]
+    pub width: i32,
+
+    /// Height of the board
+    #[arg(long, default_value_t = BOARD_HEIGHT, value_parser = clap::value_parser!(i32).range(MIN_BOARD_HEIGHT as i64..=200))]
+    pub height: i32,
+
+    /// Make the board as large as the terminal allows
+    #[arg(long, conflicts_with_all = ["width", "height"])]
+    pub fit: bool,
+
+    /// Colours of the robots, junk and items
+    #[arg(long, value_enum, default_value_t = ThemeName::Default)]
+    pub theme: ThemeName,
+
+    /// Theme file that changes the colours of the chosen theme
+    #[arg(long)]
+    pub theme_file: Option<String>,
+
+    /// Keys to play with (the arrow keys work with every layout)
+    #[arg(long, value_enum, default_value_t = Layout::Vi)]
+    pub layout: Layout,
+
+    /// Keymap file that changes the keys of the chosen layout
+    #[arg(long)]
+    pub keymap: Option<String>,
+
+    /// Glyphs to draw the board with (Unicode falls back to ASCII if the locale isn't UTF-8)
+    #[arg(long, value_enum, default_value_t = GlyphSet::Ascii)]
+    pub glyphs: GlyphSet,
+
+    /// Record the game to this replay file
+    #[arg(long)]
+    pub record: Option<String>,
+
+    /// Play back a recorded replay file
+    #[arg(long)]
+    pub replay: Option<String>,
+
+    /// Save the game to this file when quitting, so it can be resumed later
+    #[arg(long)]
+    pub save: Option<String>,
+
+    /// Resume a saved game (the save file is removed once it has been loaded)
+    #[arg(long)]
+    pub resume: Option<String>,
+
+    /// Practice with undo, the score is kept apart from the highscores
+    #[arg(long)]
+    pub practice: bool,
+
+    /// Play by the rules of BSD robots: only plain robots, no safe teleports, bombs or items
+    #[arg(long)]
+    pub classic: bool,
+
+    /// The first level with teleporting robots
+    #[arg(long, default_value_t = TELEPORTING_ROBOT_LEVEL, value_parser = clap::value_parser!(i32).range(1..))]
+    pub teleporting_robot_level: i32,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List highscores, filtered and sorted
+    Highscore(HighscoreQuery),
+    /// Show the statistics of a player
+    Stats(StatsQuery),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HighscoreQuery {
+    /// Number of highscores to show
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+
+    /// Only show highscores of this user
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Only show highscores from this date (YYYY-MM-DD) onwards
+    #[arg(long, value_parser = crate::highscore::parse_date)]
+    pub since: Option<u64>,
+
+    /// Sort the highscores by
+    #[arg(long, value_enum, default_value_t = SortKey::Score)]
+    pub sort: SortKey,
+
+    /// Only show the best highscore of every user
+    #[arg(long)]
+    pub best: bool,
+
+    /// Only show highscores of this mode
+    #[arg(long, value_enum, default_value_t = GameMode::Normal)]
+    pub mode: GameMode,
+
+    /// How to print the highscores
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ThemeName {
+    /// No colours at all, like the classic game
+    Mono,
+    Default,
+    HighContrast,
+    /// Colours that can be told apart with any kind of colour blindness
+    ColourBlind,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// y k u h l b j n, like the classic game
+    Vi,
+    /// The digits around 5, which waits a turn
+    Numpad,
+    /// q w e a d z x c, with s to wait a turn
+    Wasd,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum GlyphSet {
+    Ascii,
+    Unicode,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Score,
+    Level,
+    Date,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum GameMode {
+    Normal,
+    /// With undo
+    Practice,
+    /// By the rules of BSD robots
+    Classic,
+    /// By the rules of BSD robots, with undo
+    ClassicPractice,
+}
+
+impl GameMode {
+    /// The name of the mode in the highscore file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Normal => "normal",
+            GameMode::Practice => "practice",
+            GameMode::Classic => "classic",
+            GameMode::ClassicPractice => "classic-practice",
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct StatsQuery {
+    /// The user to show the statistics of
+    #[arg(long)]
+    pub user: String,
+
+    /// Only count the games of this mode
+    #[arg(long, value_enum, default_value_t = GameMode::Normal)]
+    pub mode: GameMode,
 }
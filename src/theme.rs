@@ -0,0 +1,417 @@
+/// Module for the colour themes of the board.
+///
+/// A theme gives every kind of thing on the board a foreground and background colour. There are
+/// a few built-in themes to pick from with `--theme`, and a theme file can change any of them.
+///
+/// The theme also holds the glyphs things are drawn with. Next to the classic ASCII glyphs there
+/// is a Unicode set, with box-drawing borders and a symbol per robot kind. It is only used when
+/// the locale says the terminal speaks UTF-8, otherwise the game falls back to ASCII.
+///
+/// The theme file has one `entity=foreground` or `entity=foreground;background` pair per line.
+/// Colours are the names crossterm knows (such as `red` or `dark_grey`), an ANSI colour number
+/// from 0 to 255, `#rrggbb`, or `default` for the colour of the terminal. Empty lines and lines
+/// starting with `#` are skipped.
+use crate::frame::Style;
+use crate::robot_kind::RobotKind;
+use crate::structs::{GlyphSet, ThemeName};
+use crossterm::style::Color;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Entity {
+    Player,
+    DeadPlayer,
+    /// A robot, which looks the way its kind says
+    Robot(RobotKind),
+    /// An armoured robot that lost its armour
+    DamagedRobot,
+    Junk,
+    SafeTeleportItem,
+    BombItem,
+    Explosion,
+    /// Whatever caught the player, highlighted on the final board
+    Culprit,
+}
+
+impl Entity {
+    const OTHERS: [Entity; 8] = [
+        Entity::Player,
+        Entity::DeadPlayer,
+        Entity::DamagedRobot,
+        Entity::Junk,
+        Entity::SafeTeleportItem,
+        Entity::BombItem,
+        Entity::Explosion,
+        Entity::Culprit,
+    ];
+
+    // Every entity: the robots of every kind and all the other things
+    fn all() -> impl Iterator<Item = Entity> {
+        RobotKind::ALL
+            .into_iter()
+            .map(Entity::Robot)
+            .chain(Entity::OTHERS)
+    }
+
+    /// The name of the entity in theme files.
+    fn name(&self) -> String {
+        let name = match self {
+            Entity::Player => "player",
+            Entity::DeadPlayer => "dead_player",
+            Entity::Robot(kind) => return kind.name().replace(' ', "_"),
+            Entity::DamagedRobot => "damaged_robot",
+            Entity::Junk => "junk",
+            Entity::SafeTeleportItem => "safe_teleport_item",
+            Entity::BombItem => "bomb_item",
+            Entity::Explosion => "explosion",
+            Entity::Culprit => "culprit",
+        };
+        name.to_string()
+    }
+
+    // The ASCII and the Unicode glyph of the entity. The culprit keeps the glyph of whatever it
+    // is, only its colours change.
+    fn glyphs(&self) -> (char, char) {
+        match self {
+            Entity::Player => ('@', '@'),
+            Entity::DeadPlayer => ('%', '✖'),
+            Entity::Robot(kind) => kind.glyphs(),
+            Entity::DamagedRobot => ('a', '□'),
+            Entity::Junk => ('#', '▒'),
+            Entity::SafeTeleportItem => ('S', '✧'),
+            Entity::BombItem => ('B', '◎'),
+            Entity::Explosion => ('{', '✺'),
+            Entity::Culprit => ('%', '✖'),
+        }
+    }
+}
+
+pub struct Border {
+    pub horizontal: char,
+    pub vertical: char,
+    pub top_left: char,
+    pub top_right: char,
+    pub bottom_left: char,
+    pub bottom_right: char,
+}
+
+pub struct Theme {
+    styles: HashMap<Entity, Style>,
+    unicode: bool,
+    pub border: Border,
+}
+
+impl Theme {
+    pub fn new(name: ThemeName, glyph_set: GlyphSet) -> Theme {
+        let unicode = glyph_set == GlyphSet::Unicode && locale_is_utf8();
+        let mut theme = Theme {
+            styles: HashMap::new(),
+            unicode,
+            border: if unicode {
+                Border {
+                    horizontal: '─',
+                    vertical: '│',
+                    top_left: '┌',
+                    top_right: '┐',
+                    bottom_left: '└',
+                    bottom_right: '┘',
+                }
+            } else {
+                Border {
+                    horizontal: '-',
+                    vertical: '|',
+                    top_left: '/',
+                    top_right: '\\',
+                    bottom_left: '\\',
+                    bottom_right: '/',
+                }
+            },
+        };
+        let colours: Vec<(Entity, Option<Color>, Option<Color>)> = match name {
+            ThemeName::Mono => return theme,
+            ThemeName::Default => vec![
+                (Entity::Player, Some(Color::Cyan), None),
+                (Entity::DeadPlayer, Some(Color::Red), None),
+                (Entity::Robot(RobotKind::Robot), Some(Color::Yellow), None),
+                (
+                    Entity::Robot(RobotKind::SuperRobot),
+                    Some(Color::Magenta),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::KillerRobot),
+                    Some(Color::Red),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::TeleportingRobot),
+                    Some(Color::DarkGreen),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::ArmouredRobot),
+                    Some(Color::White),
+                    None,
+                ),
+                (Entity::DamagedRobot, Some(Color::Yellow), None),
+                (
+                    Entity::Robot(RobotKind::JunkPusher),
+                    Some(Color::DarkCyan),
+                    None,
+                ),
+                (Entity::Junk, Some(Color::DarkGrey), None),
+                (Entity::SafeTeleportItem, Some(Color::Green), None),
+                (Entity::BombItem, Some(Color::Blue), None),
+                (Entity::Explosion, Some(Color::DarkYellow), None),
+                (Entity::Culprit, Some(Color::White), Some(Color::Red)),
+            ],
+            ThemeName::HighContrast => vec![
+                (Entity::Player, Some(Color::Black), Some(Color::White)),
+                (Entity::DeadPlayer, Some(Color::White), Some(Color::Red)),
+                (Entity::Robot(RobotKind::Robot), Some(Color::Yellow), None),
+                (
+                    Entity::Robot(RobotKind::SuperRobot),
+                    Some(Color::Magenta),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::KillerRobot),
+                    Some(Color::Red),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::TeleportingRobot),
+                    Some(Color::Green),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::ArmouredRobot),
+                    Some(Color::White),
+                    None,
+                ),
+                (Entity::DamagedRobot, Some(Color::Yellow), None),
+                (
+                    Entity::Robot(RobotKind::JunkPusher),
+                    Some(Color::Cyan),
+                    None,
+                ),
+                (Entity::Junk, Some(Color::White), None),
+                (
+                    Entity::SafeTeleportItem,
+                    Some(Color::Black),
+                    Some(Color::Green),
+                ),
+                (Entity::BombItem, Some(Color::Black), Some(Color::Cyan)),
+                (Entity::Explosion, Some(Color::Black), Some(Color::Yellow)),
+                (Entity::Culprit, Some(Color::White), Some(Color::Magenta)),
+            ],
+            // The Okabe-Ito palette, which stays apart for all common kinds of colour blindness
+            ThemeName::ColourBlind => vec![
+                (Entity::Player, Some(rgb(86, 180, 233)), None),
+                (Entity::DeadPlayer, Some(rgb(213, 94, 0)), None),
+                (
+                    Entity::Robot(RobotKind::Robot),
+                    Some(rgb(230, 159, 0)),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::SuperRobot),
+                    Some(rgb(204, 121, 167)),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::KillerRobot),
+                    Some(rgb(213, 94, 0)),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::TeleportingRobot),
+                    Some(rgb(240, 228, 66)),
+                    None,
+                ),
+                (
+                    Entity::Robot(RobotKind::ArmouredRobot),
+                    Some(Color::White),
+                    None,
+                ),
+                (Entity::DamagedRobot, Some(rgb(230, 159, 0)), None),
+                (
+                    Entity::Robot(RobotKind::JunkPusher),
+                    Some(rgb(0, 114, 178)),
+                    None,
+                ),
+                (Entity::Junk, Some(Color::Grey), None),
+                (Entity::SafeTeleportItem, Some(rgb(0, 158, 115)), None),
+                (Entity::BombItem, Some(rgb(0, 114, 178)), None),
+                (Entity::Explosion, Some(rgb(240, 228, 66)), None),
+                (Entity::Culprit, Some(Color::Black), Some(rgb(213, 94, 0))),
+            ],
+        };
+        for (entity, foreground, background) in colours {
+            theme.styles.insert(
+                entity,
+                Style {
+                    foreground,
+                    background,
+                },
+            );
+        }
+        theme
+    }
+
+    /// Changes the colours of the entities that are listed in a theme file.
+    pub fn load(mut self, path: &str) -> Result<Theme, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read theme file {}: {}", path, err))?;
+        for (line, number) in content.lines().zip(1..) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = || format!("Malformed line {} in theme file {}", number, path);
+            let (name, value) = line.split_once('=').ok_or_else(malformed)?;
+            let entity = Entity::all()
+                .find(|entity| entity.name() == name.trim())
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown entity {} on line {} in theme file {}",
+                        name.trim(),
+                        number,
+                        path
+                    )
+                })?;
+            let colours = value
+                .split(';')
+                .map(|colour| {
+                    parse_colour(colour).ok_or_else(|| {
+                        format!(
+                            "Unknown colour {} on line {} in theme file {}",
+                            colour.trim(),
+                            number,
+                            path
+                        )
+                    })
+                })
+                .collect::<Result<Vec<Option<Color>>, String>>()?;
+            let style = match colours[..] {
+                [foreground] => Style {
+                    foreground,
+                    background: None,
+                },
+                [foreground, background] => Style {
+                    foreground,
+                    background,
+                },
+                _ => return Err(malformed()),
+            };
+            self.styles.insert(entity, style);
+        }
+        Ok(self)
+    }
+
+    pub fn style(&self, entity: Entity) -> Style {
+        self.styles.get(&entity).copied().unwrap_or_default()
+    }
+
+    pub fn glyph(&self, entity: Entity) -> char {
+        let (ascii, unicode) = entity.glyphs();
+        if self.unicode {
+            unicode
+        } else {
+            ascii
+        }
+    }
+}
+
+// Whether the locale uses UTF-8, going by the same variables the C library looks at
+fn locale_is_utf8() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()));
+    match locale {
+        Some(locale) => {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        }
+        None => false,
+    }
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+// Parses a colour from a theme file. `Some(None)` is the colour of the terminal.
+fn parse_colour(value: &str) -> Option<Option<Color>> {
+    let value = value.trim();
+    if value == "default" {
+        return Some(None);
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Some(rgb(channel(0)?, channel(2)?, channel(4)?)));
+    }
+    if let Ok(number) = value.parse::<u8>() {
+        return Some(Some(Color::AnsiValue(number)));
+    }
+    Color::try_from(value).ok().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loads a theme file on top of the theme without colours
+    fn load(name: &str, content: &str) -> Result<Theme, String> {
+        let path = std::env::temp_dir().join(format!("robots-{}-{}", std::process::id(), name));
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, content).unwrap();
+        let theme = Theme::new(ThemeName::Mono, GlyphSet::Ascii).load(&path);
+        fs::remove_file(&path).unwrap();
+        theme
+    }
+
+    #[test]
+    fn colours_are_parsed_by_name_number_or_hex() {
+        assert_eq!(parse_colour("default"), Some(None));
+        assert_eq!(parse_colour(" red "), Some(Some(Color::Red)));
+        assert_eq!(parse_colour("dark_grey"), Some(Some(Color::DarkGrey)));
+        assert_eq!(parse_colour("208"), Some(Some(Color::AnsiValue(208))));
+        assert_eq!(parse_colour("#ff8000"), Some(Some(rgb(255, 128, 0))));
+        for colour in ["#ff80", "#ff80zz", "256", "mauve", ""] {
+            assert_eq!(parse_colour(colour), None, "{}", colour);
+        }
+    }
+
+    #[test]
+    fn a_theme_file_changes_the_colours_it_lists() {
+        let theme = load(
+            "theme",
+            "# Brighter junk\n\njunk = yellow ; #202020\nplayer=default\n",
+        )
+        .unwrap();
+        let junk = Style {
+            foreground: Some(Color::Yellow),
+            background: Some(rgb(32, 32, 32)),
+        };
+        assert_eq!(theme.style(Entity::Junk), junk);
+        assert_eq!(theme.style(Entity::Player), Style::default());
+    }
+
+    #[test]
+    fn malformed_theme_files_are_refused() {
+        let err = load("no-colour", "junk\n").err().unwrap();
+        assert!(err.starts_with("Malformed line 1"), "{}", err);
+        let err = load("three-colours", "junk=red;red;red\n").err().unwrap();
+        assert!(err.starts_with("Malformed line 1"), "{}", err);
+        let err = load("unknown-entity", "\nwall=red\n").err().unwrap();
+        assert!(err.starts_with("Unknown entity wall on line 2"), "{}", err);
+        let err = load("unknown-colour", "junk=mauve\n").err().unwrap();
+        assert!(err.starts_with("Unknown colour mauve on line 1"), "{}", err);
+    }
+}